@@ -0,0 +1,502 @@
+//! A flat representation of the tree: the nodes live in a `Vec` and are
+//! linked to their parent, siblings and children by [`NodeId`], so that a
+//! node can be looked up, inserted, removed or moved without walking the
+//! tree.
+
+use ast::*;
+use std::mem;
+use std::ops::{Index, IndexMut};
+
+/// The index of a node in its [`Arena`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct NodeId(usize);
+
+/// The data of a node in the arena. The `nodes` of roots, rules and at-rules
+/// are kept empty, as the children are linked through the arena instead; an
+/// at-rule with a block has `Some` empty nodes.
+#[derive(Debug, PartialEq)]
+pub enum NodeData<'a> {
+    Root(Root<'a>),
+    Rule(Rule<'a>),
+    AtRule(AtRule<'a>),
+    Declaration(Declaration<'a>),
+    Comment(Comment<'a>),
+}
+
+impl NodeData<'_> {
+    fn kind(&self) -> &'static str {
+        match self {
+            NodeData::Root(_) => "root",
+            NodeData::Rule(_) => "rule",
+            NodeData::AtRule(_) => "at-rule",
+            NodeData::Declaration(_) => "declaration",
+            NodeData::Comment(_) => "comment",
+        }
+    }
+
+    /// Whether `child` can be a child of this node.
+    fn accepts(&self, child: &NodeData) -> bool {
+        match (self, child) {
+            (_, NodeData::Root(_)) => false,
+            (NodeData::Root(_), NodeData::Declaration(_)) => false,
+            (NodeData::Root(_) | NodeData::Rule(_) | NodeData::AtRule(_), _) => true,
+            _ => false,
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Slot<'a> {
+    data: NodeData<'a>,
+    parent: Option<NodeId>,
+    prev: Option<NodeId>,
+    next: Option<NodeId>,
+    first_child: Option<NodeId>,
+    last_child: Option<NodeId>,
+}
+
+/// A tree stored in a flat list of nodes.
+///
+/// Nodes removed with [`Arena::detach`] stay in the arena, so their ids
+/// remain valid and they can be inserted again elsewhere.
+#[derive(Debug)]
+pub struct Arena<'a> {
+    slots: Vec<Slot<'a>>,
+    root: NodeId,
+}
+
+impl<'a> Arena<'a> {
+    pub fn new(root: Root<'a>) -> Self {
+        let mut arena = Arena {
+            slots: Vec::new(),
+            root: NodeId(0),
+        };
+        arena.root = arena.create(root);
+        arena
+    }
+
+    pub fn root(&self) -> NodeId {
+        self.root
+    }
+
+    pub fn parent(&self, id: NodeId) -> Option<NodeId> {
+        self.slots[id.0].parent
+    }
+
+    pub fn prev_sibling(&self, id: NodeId) -> Option<NodeId> {
+        self.slots[id.0].prev
+    }
+
+    pub fn next_sibling(&self, id: NodeId) -> Option<NodeId> {
+        self.slots[id.0].next
+    }
+
+    pub fn first_child(&self, id: NodeId) -> Option<NodeId> {
+        self.slots[id.0].first_child
+    }
+
+    pub fn last_child(&self, id: NodeId) -> Option<NodeId> {
+        self.slots[id.0].last_child
+    }
+
+    pub fn children(&self, id: NodeId) -> Children<'_, 'a> {
+        Children {
+            arena: self,
+            next: self.first_child(id),
+        }
+    }
+
+    /// Adds `node` and its children to the arena, detached from the tree.
+    pub fn create(&mut self, node: impl Into<NodeData<'a>>) -> NodeId {
+        let mut data = node.into();
+        let children: Vec<NodeData> = match &mut data {
+            NodeData::Root(root) => mem::take(&mut root.nodes)
+                .into_iter()
+                .map(NodeData::from)
+                .collect(),
+            NodeData::Rule(rule) => mem::take(&mut rule.nodes)
+                .into_iter()
+                .map(NodeData::from)
+                .collect(),
+            NodeData::AtRule(AtRule {
+                nodes: Some(nodes), ..
+            }) => mem::take(nodes).into_iter().map(NodeData::from).collect(),
+            _ => Vec::new(),
+        };
+
+        let id = NodeId(self.slots.len());
+        self.slots.push(Slot {
+            data,
+            parent: None,
+            prev: None,
+            next: None,
+            first_child: None,
+            last_child: None,
+        });
+        for child in children {
+            let child = self.create(child);
+            self.append(id, child);
+        }
+
+        id
+    }
+
+    /// Inserts the detached node `child` as the last child of `parent`.
+    pub fn append(&mut self, parent: NodeId, child: NodeId) {
+        let prev = self.last_child(parent);
+        self.link(parent, prev, None, child);
+    }
+
+    /// Inserts the detached node `child` as the first child of `parent`.
+    pub fn prepend(&mut self, parent: NodeId, child: NodeId) {
+        let next = self.first_child(parent);
+        self.link(parent, None, next, child);
+    }
+
+    /// Inserts the detached node `node` right before `sibling`.
+    pub fn insert_before(&mut self, sibling: NodeId, node: NodeId) {
+        let parent = self.parent(sibling).expect("sibling is not in the tree");
+        let prev = self.prev_sibling(sibling);
+        self.link(parent, prev, Some(sibling), node);
+    }
+
+    /// Inserts the detached node `node` right after `sibling`.
+    pub fn insert_after(&mut self, sibling: NodeId, node: NodeId) {
+        let parent = self.parent(sibling).expect("sibling is not in the tree");
+        let next = self.next_sibling(sibling);
+        self.link(parent, Some(sibling), next, node);
+    }
+
+    /// Removes `id`, with its children, from its parent.
+    pub fn detach(&mut self, id: NodeId) {
+        let Some(parent) = self.slots[id.0].parent.take() else {
+            return;
+        };
+        let prev = self.slots[id.0].prev.take();
+        let next = self.slots[id.0].next.take();
+
+        match prev {
+            Some(prev) => self.slots[prev.0].next = next,
+            None => self.slots[parent.0].first_child = next,
+        }
+        match next {
+            Some(next) => self.slots[next.0].prev = prev,
+            None => self.slots[parent.0].last_child = prev,
+        }
+    }
+
+    /// Links `node` under `parent` between the siblings `prev` and `next`.
+    fn link(&mut self, parent: NodeId, prev: Option<NodeId>, next: Option<NodeId>, node: NodeId) {
+        assert!(
+            self.parent(node).is_none() && node != self.root,
+            "node is already in the tree"
+        );
+        let (parent_data, node_data) = (&self[parent], &self[node]);
+        assert!(
+            parent_data.accepts(node_data),
+            "a {} can't be a child of a {}",
+            node_data.kind(),
+            parent_data.kind()
+        );
+        let mut ancestor = Some(parent);
+        while let Some(id) = ancestor {
+            assert!(id != node, "a node can't be inserted into itself");
+            ancestor = self.parent(id);
+        }
+
+        if let NodeData::AtRule(at_rule) = &mut self[parent] {
+            at_rule.nodes.get_or_insert_with(Vec::new);
+        }
+        let slot = &mut self.slots[node.0];
+        slot.parent = Some(parent);
+        slot.prev = prev;
+        slot.next = next;
+        match prev {
+            Some(prev) => self.slots[prev.0].next = Some(node),
+            None => self.slots[parent.0].first_child = Some(node),
+        }
+        match next {
+            Some(next) => self.slots[next.0].prev = Some(node),
+            None => self.slots[parent.0].last_child = Some(node),
+        }
+    }
+
+    /// Rebuilds the tree, leaving out the detached nodes.
+    ///
+    /// Panics if the data of a node was replaced with a kind its place in the
+    /// tree doesn't allow, like a declaration in the root.
+    pub fn into_root(self) -> Root<'a> {
+        let root = self.root;
+        let mut slots = self
+            .slots
+            .into_iter()
+            .map(|slot| (Some(slot.data), slot.first_child, slot.next))
+            .collect::<Vec<_>>();
+
+        match take_tree(&mut slots, root) {
+            NodeData::Root(root) => root,
+            data => panic!("a {} can't be the root", data.kind()),
+        }
+    }
+}
+
+type Taken<'a> = (Option<NodeData<'a>>, Option<NodeId>, Option<NodeId>);
+
+fn take_tree<'a>(slots: &mut [Taken<'a>], id: NodeId) -> NodeData<'a> {
+    let mut data = slots[id.0].0.take().expect("node taken twice");
+    let mut children = Vec::new();
+    let mut next = slots[id.0].1;
+    while let Some(child) = next {
+        let child_data = take_tree(slots, child);
+        assert!(
+            data.accepts(&child_data),
+            "a {} can't be a child of a {}",
+            child_data.kind(),
+            data.kind()
+        );
+        children.push(child_data);
+        next = slots[child.0].2;
+    }
+    if children.is_empty() {
+        return data;
+    }
+
+    match &mut data {
+        NodeData::Root(root) => {
+            root.nodes = children.into_iter().map(root_child).collect();
+        }
+        NodeData::Rule(rule) => {
+            rule.nodes = children.into_iter().map(block_child).collect();
+        }
+        NodeData::AtRule(at_rule) => {
+            at_rule.nodes = Some(children.into_iter().map(block_child).collect());
+        }
+        _ => unreachable!(),
+    }
+    data
+}
+
+fn root_child(data: NodeData) -> RootChild {
+    match data {
+        NodeData::Rule(rule) => RootChild::Rule(rule),
+        NodeData::AtRule(at_rule) => RootChild::AtRule(at_rule),
+        NodeData::Comment(comment) => RootChild::Comment(comment),
+        _ => unreachable!(),
+    }
+}
+
+fn block_child(data: NodeData) -> BlockChild {
+    match data {
+        NodeData::Rule(rule) => BlockChild::Rule(rule),
+        NodeData::AtRule(at_rule) => BlockChild::AtRule(at_rule),
+        NodeData::Declaration(decl) => BlockChild::Declaration(decl),
+        NodeData::Comment(comment) => BlockChild::Comment(comment),
+        NodeData::Root(_) => unreachable!(),
+    }
+}
+
+impl<'a> Index<NodeId> for Arena<'a> {
+    type Output = NodeData<'a>;
+
+    fn index(&self, id: NodeId) -> &NodeData<'a> {
+        &self.slots[id.0].data
+    }
+}
+
+impl IndexMut<NodeId> for Arena<'_> {
+    fn index_mut(&mut self, id: NodeId) -> &mut Self::Output {
+        &mut self.slots[id.0].data
+    }
+}
+
+/// The children of a node, first to last.
+pub struct Children<'b, 'a> {
+    arena: &'b Arena<'a>,
+    next: Option<NodeId>,
+}
+
+impl Iterator for Children<'_, '_> {
+    type Item = NodeId;
+
+    fn next(&mut self) -> Option<NodeId> {
+        let id = self.next?;
+        self.next = self.arena.next_sibling(id);
+        Some(id)
+    }
+}
+
+macro_rules! impl_from {
+    ($($ty: ident),*) => {
+        $(impl<'a> From<$ty<'a>> for NodeData<'a> {
+            fn from(node: $ty<'a>) -> Self {
+                NodeData::$ty(node)
+            }
+        })*
+    };
+}
+
+impl_from!(Root, Rule, AtRule, Declaration, Comment);
+
+impl<'a> From<RootChild<'a>> for NodeData<'a> {
+    fn from(child: RootChild<'a>) -> Self {
+        match child {
+            RootChild::Rule(rule) => NodeData::Rule(rule),
+            RootChild::AtRule(at_rule) => NodeData::AtRule(at_rule),
+            RootChild::Comment(comment) => NodeData::Comment(comment),
+        }
+    }
+}
+
+impl<'a> From<BlockChild<'a>> for NodeData<'a> {
+    fn from(child: BlockChild<'a>) -> Self {
+        match child {
+            BlockChild::Rule(rule) => NodeData::Rule(rule),
+            BlockChild::AtRule(at_rule) => NodeData::AtRule(at_rule),
+            BlockChild::Declaration(decl) => NodeData::Declaration(decl),
+            BlockChild::Comment(comment) => NodeData::Comment(comment),
+        }
+    }
+}
+
+impl Node for NodeData<'_> {
+    fn start(&self) -> usize {
+        match self {
+            NodeData::Root(root) => root.start,
+            NodeData::Rule(rule) => rule.start,
+            NodeData::AtRule(at_rule) => at_rule.start,
+            NodeData::Declaration(decl) => decl.start,
+            NodeData::Comment(comment) => comment.start,
+        }
+    }
+
+    fn end(&self) -> usize {
+        match self {
+            NodeData::Root(root) => root.end,
+            NodeData::Rule(rule) => rule.end,
+            NodeData::AtRule(at_rule) => at_rule.end,
+            NodeData::Declaration(decl) => decl.end,
+            NodeData::Comment(comment) => comment.end,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use macros::*;
+    use parser::Parser;
+
+    const CSS: &str = "/* c */ a { b: c; @media print { d { e: f } } } @import 'x';";
+
+    #[test]
+    fn round_trip() {
+        let arena = Arena::new(Parser::new(CSS).parse().unwrap());
+
+        assert_eq!(arena.into_root(), Parser::new(CSS).parse().unwrap());
+    }
+
+    #[test]
+    fn links() {
+        let arena = Arena::new(Parser::new(CSS).parse().unwrap());
+        let root = arena.root();
+
+        let children = arena.children(root).collect::<Vec<_>>();
+        assert_eq!(children.len(), 3);
+        assert!(matches!(arena[children[0]], NodeData::Comment(_)));
+        assert_eq!(arena.next_sibling(children[0]), Some(children[1]));
+        assert_eq!(arena.prev_sibling(children[1]), Some(children[0]));
+        assert_eq!(arena.last_child(root), Some(children[2]));
+        assert_eq!(arena.first_child(children[2]), None);
+
+        let media = arena.last_child(children[1]).unwrap();
+        let d = arena.first_child(media).unwrap();
+        let e = arena.first_child(d).unwrap();
+        assert!(matches!(&arena[e], NodeData::Declaration(decl) if decl.prop == "e"));
+        assert_eq!(arena.parent(e), Some(d));
+        assert_eq!(arena.parent(d), Some(media));
+        assert_eq!(arena.parent(root), None);
+    }
+
+    #[test]
+    fn edit() {
+        let mut arena = Arena::new(Parser::new("a{b:c;d:e}f{}").parse().unwrap());
+        let root = arena.root();
+        let a = arena.first_child(root).unwrap();
+        let f = arena.next_sibling(a).unwrap();
+        let b = arena.first_child(a).unwrap();
+        let d = arena.next_sibling(b).unwrap();
+
+        arena.detach(b);
+        arena.append(f, b);
+        let g = arena.create(Declaration {
+            prop: "g".into(),
+            value: "h".into(),
+            important: false,
+            raws: None,
+            start: 0,
+            end: 0,
+        });
+        arena.insert_before(d, g);
+        let i = arena.create(Parser::new("i{j:k}").parse_rule().unwrap());
+        arena.insert_after(f, i);
+        arena.detach(f);
+        arena.prepend(root, f);
+        if let NodeData::Rule(rule) = &mut arena[a] {
+            rule.selector = "l".into();
+        }
+
+        let mut root = arena.into_root();
+        root.clear_raws();
+        assert_eq!(
+            root,
+            root!(
+                0,
+                13,
+                vec![
+                    root_rule!(10, 13, "f", vec![decl!(2, 5, "b", "c")]),
+                    root_rule!(
+                        0,
+                        10,
+                        "l",
+                        vec![decl!(0, 0, "g", "h"), decl!(6, 9, "d", "e")]
+                    ),
+                    root_rule!(0, 6, "i", vec![decl!(2, 5, "j", "k")]),
+                ]
+            )
+        );
+    }
+
+    #[test]
+    fn at_rule_block() {
+        let mut arena = Arena::new(Parser::new("@media print;").parse().unwrap());
+        let media = arena.first_child(arena.root()).unwrap();
+        let a = arena.create(Parser::new("a{}").parse_rule().unwrap());
+        arena.append(media, a);
+
+        let root = arena.into_root();
+        let RootChild::AtRule(at_rule) = &root.nodes[0] else {
+            panic!("expected an at-rule");
+        };
+        assert_eq!(at_rule.nodes.as_ref().map(Vec::len), Some(1));
+    }
+
+    #[test]
+    #[should_panic(expected = "a declaration can't be a child of a root")]
+    fn declaration_in_root() {
+        let mut arena = Arena::new(Parser::new("a{b:c}").parse().unwrap());
+        let a = arena.first_child(arena.root()).unwrap();
+        let b = arena.first_child(a).unwrap();
+        arena.detach(b);
+        arena.append(arena.root(), b);
+    }
+
+    #[test]
+    #[should_panic(expected = "a node can't be inserted into itself")]
+    fn insert_into_descendant() {
+        let mut arena = Arena::new(Parser::new("a{b{}}").parse().unwrap());
+        let a = arena.first_child(arena.root()).unwrap();
+        let b = arena.first_child(a).unwrap();
+        arena.detach(a);
+        arena.append(b, a);
+    }
+}
@@ -1,56 +1,395 @@
-#[derive(Debug, PartialEq)]
-pub struct Comment {
-    pub text: String,
+//! Every node spans the byte range `start..end` of the parsed source. The
+//! text of the nodes borrows from the source as long as it is not changed.
+
+use std::borrow::Cow;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Comment<'a> {
+    pub text: Cow<'a, str>,
+    pub raws: Option<CommentRaws<'a>>,
     pub start: usize,
     pub end: usize,
 }
 
-#[derive(Debug, PartialEq)]
-pub struct Declaration {
-    pub prop: String,
-    pub value: String,
+#[derive(Clone, Debug, PartialEq)]
+pub struct Declaration<'a> {
+    pub prop: Cow<'a, str>,
+    pub value: Cow<'a, str>,
+    pub important: bool,
+    pub raws: Option<DeclarationRaws<'a>>,
     pub start: usize,
     pub end: usize,
 }
 
-#[derive(Debug, PartialEq)]
-pub struct AtRule {
-    pub name: String,
-    pub params: String,
-    pub nodes: Vec<BlockChild>,
+#[derive(Clone, Debug, PartialEq)]
+pub struct AtRule<'a> {
+    pub name: Cow<'a, str>,
+    pub params: Cow<'a, str>,
+    /// `None` for statements like `@import "x.css";` that have no block.
+    pub nodes: Option<Vec<BlockChild<'a>>>,
+    pub raws: Option<AtRuleRaws<'a>>,
     pub start: usize,
     pub end: usize,
 }
 
-#[derive(Debug, PartialEq)]
-pub enum BlockChild {
-    AtRule(AtRule),
-    Declaration(Declaration),
-    Comment(Comment),
+#[derive(Clone, Debug, PartialEq)]
+pub enum BlockChild<'a> {
+    Rule(Rule<'a>),
+    AtRule(AtRule<'a>),
+    Declaration(Declaration<'a>),
+    Comment(Comment<'a>),
 }
 
-#[derive(Debug, PartialEq)]
-pub struct Rule {
-    pub selector: String,
-    pub nodes: Vec<BlockChild>,
+#[derive(Clone, Debug, PartialEq)]
+pub struct Rule<'a> {
+    pub selector: Cow<'a, str>,
+    pub nodes: Vec<BlockChild<'a>>,
+    pub raws: Option<RuleRaws<'a>>,
     pub start: usize,
     pub end: usize,
 }
 
-#[derive(Debug, PartialEq)]
-pub enum RootChild {
-    Rule(Rule),
-    AtRule(AtRule),
-    Comment(Comment),
+#[derive(Clone, Debug, PartialEq)]
+pub enum RootChild<'a> {
+    Rule(Rule<'a>),
+    AtRule(AtRule<'a>),
+    Comment(Comment<'a>),
 }
 
-#[derive(Debug, PartialEq)]
-pub struct Root {
-    pub nodes: Vec<RootChild>,
+#[derive(Clone, Debug, PartialEq)]
+pub struct Root<'a> {
+    pub nodes: Vec<RootChild<'a>>,
+    pub raws: Option<RootRaws<'a>>,
     pub start: usize,
     pub end: usize,
 }
 
+/// The source text of a node field next to the cleaned `value` it was
+/// parsed into. The raw text is only meaningful while the field still
+/// equals `value`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Raw<'a> {
+    pub value: Cow<'a, str>,
+    pub raw: Cow<'a, str>,
+}
+
+impl Raw<'_> {
+    /// `raw` if `current` is still the parsed value, `current` otherwise.
+    pub fn get<'b>(raw: &'b Option<Raw>, current: &'b str) -> &'b str {
+        match raw {
+            Some(Raw { value, raw }) if value == current => raw,
+            _ => current,
+        }
+    }
+}
+
+// The raws keep the formatting the parser dropped so that the source can be
+// reproduced exactly. Nodes built by hand have no raws. Empty declarations,
+// i.e. stray `;` in a block, are kept in the `before` of the next node or the
+// `after` of the block.
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct CommentRaws<'a> {
+    /// Whitespace before the comment.
+    pub before: Cow<'a, str>,
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct DeclarationRaws<'a> {
+    pub before: Cow<'a, str>,
+    /// Everything between the property and the value, colon included.
+    pub between: Cow<'a, str>,
+    /// The value with the comments trailing it.
+    pub value: Option<Raw<'a>>,
+    /// The `!important` flag as written, starting right after the raw value,
+    /// as in ` ! /* x */ IMPORTANT`.
+    pub important: Option<Cow<'a, str>>,
+    /// Whitespace between the value and the `;`, `}` or end of input.
+    pub after: Cow<'a, str>,
+    pub semicolon: bool,
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct RuleRaws<'a> {
+    pub before: Cow<'a, str>,
+    /// The selector with its comments.
+    pub selector: Option<Raw<'a>>,
+    /// Whitespace between the selector and `{`.
+    pub between: Cow<'a, str>,
+    /// Whitespace between the last child and `}`.
+    pub after: Cow<'a, str>,
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct AtRuleRaws<'a> {
+    pub before: Cow<'a, str>,
+    /// Whitespace and comments between the name and the params.
+    pub after_name: Cow<'a, str>,
+    /// The params with their comments.
+    pub params: Option<Raw<'a>>,
+    /// Whitespace between the params and `{`, `;`, `}` or end of input.
+    pub between: Cow<'a, str>,
+    /// Whitespace between the last child and `}`.
+    pub after: Cow<'a, str>,
+    /// Whether a bodiless at-rule is terminated by `;`.
+    pub semicolon: bool,
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct RootRaws<'a> {
+    /// Whitespace after the last node.
+    pub after: Cow<'a, str>,
+}
+
+impl Comment<'_> {
+    /// The URL of a `/*# sourceMappingURL=... */` annotation.
+    pub fn source_map_url(&self) -> Option<&str> {
+        let text = self.text.trim_start();
+        let text = text.strip_prefix('#').or_else(|| text.strip_prefix('@'))?;
+        let url = text.trim_start().strip_prefix("sourceMappingURL=")?;
+
+        url.split_whitespace().next()
+    }
+}
+
+impl<'a> Root<'a> {
+    /// The URL of the map of the source the tree was parsed from, given by
+    /// its last `sourceMappingURL` annotation.
+    pub fn source_map_url(&self) -> Option<&str> {
+        self.nodes.iter().rev().find_map(|child| match child {
+            RootChild::Comment(comment) => comment.source_map_url(),
+            _ => None,
+        })
+    }
+
+    /// Drops the raws of the whole tree, so that it compares equal to a tree
+    /// built by hand and prints in the default style.
+    pub fn clear_raws(&mut self) {
+        self.raws = None;
+        for child in self.nodes.iter_mut() {
+            match child {
+                RootChild::Rule(rule) => rule.clear_raws(),
+                RootChild::AtRule(at_rule) => at_rule.clear_raws(),
+                RootChild::Comment(comment) => comment.raws = None,
+            }
+        }
+    }
+
+    pub fn append(&mut self, node: impl Into<RootChild<'a>>) {
+        self.nodes.push(node.into());
+    }
+
+    pub fn prepend(&mut self, node: impl Into<RootChild<'a>>) {
+        self.nodes.insert(0, node.into());
+    }
+}
+
+impl<'a> Rule<'a> {
+    pub fn clear_raws(&mut self) {
+        self.raws = None;
+        clear_raws(&mut self.nodes);
+    }
+
+    pub fn append(&mut self, node: impl Into<BlockChild<'a>>) {
+        self.nodes.push(node.into());
+    }
+
+    pub fn prepend(&mut self, node: impl Into<BlockChild<'a>>) {
+        self.nodes.insert(0, node.into());
+    }
+}
+
+impl<'a> AtRule<'a> {
+    pub fn clear_raws(&mut self) {
+        self.raws = None;
+        if let Some(nodes) = &mut self.nodes {
+            clear_raws(nodes);
+        }
+    }
+
+    /// Adds `node` at the end of the block, giving the at-rule a block if it
+    /// has none.
+    pub fn append(&mut self, node: impl Into<BlockChild<'a>>) {
+        self.nodes.get_or_insert_with(Vec::new).push(node.into());
+    }
+
+    /// Adds `node` at the start of the block, giving the at-rule a block if
+    /// it has none.
+    pub fn prepend(&mut self, node: impl Into<BlockChild<'a>>) {
+        self.nodes
+            .get_or_insert_with(Vec::new)
+            .insert(0, node.into());
+    }
+}
+
+/// Drops the raws of `nodes` and of all their descendants, as
+/// `Rule::clear_raws` does for the nodes of a rule.
+pub fn clear_raws(nodes: &mut [BlockChild]) {
+    for child in nodes.iter_mut() {
+        match child {
+            BlockChild::Rule(rule) => rule.clear_raws(),
+            BlockChild::AtRule(at_rule) => at_rule.clear_raws(),
+            BlockChild::Declaration(decl) => decl.raws = None,
+            BlockChild::Comment(comment) => comment.raws = None,
+        }
+    }
+}
+
+macro_rules! impl_from {
+    ($child: ident, $($ty: ident),*) => {
+        $(impl<'a> From<$ty<'a>> for $child<'a> {
+            fn from(node: $ty<'a>) -> Self {
+                $child::$ty(node)
+            }
+        })*
+    };
+}
+
+impl_from!(BlockChild, Rule, AtRule, Declaration, Comment);
+impl_from!(RootChild, Rule, AtRule, Comment);
+
+/// A location in the source: the byte `offset` and its 1-based `line` and
+/// `column`, where columns count characters.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Position {
+    pub offset: usize,
+    pub line: usize,
+    pub column: usize,
+}
+
+/// Maps byte offsets of a source to positions. `\n`, `\r\n`, `\r` and `\f`
+/// all end a line, as in the CSS Syntax spec.
+pub struct LineIndex<'a> {
+    source: &'a str,
+    line_starts: Vec<usize>,
+}
+
+impl<'a> LineIndex<'a> {
+    pub fn new(source: &'a str) -> Self {
+        Self::with_form_feeds(source, true)
+    }
+
+    /// Like `new`, but only `\n`, `\r\n` and `\r` end a line, as source map
+    /// consumers count them.
+    pub fn for_source_map(source: &'a str) -> Self {
+        Self::with_form_feeds(source, false)
+    }
+
+    fn with_form_feeds(source: &'a str, form_feeds: bool) -> Self {
+        let bytes = source.as_bytes();
+        let mut line_starts = vec![0];
+
+        for (i, b) in bytes.iter().enumerate() {
+            match b {
+                b'\r' if bytes.get(i + 1) == Some(&b'\n') => {}
+                b'\n' | b'\r' => line_starts.push(i + 1),
+                b'\x0c' if form_feeds => line_starts.push(i + 1),
+                _ => {}
+            }
+        }
+
+        LineIndex {
+            source,
+            line_starts,
+        }
+    }
+
+    /// The position of `offset`, if it is within the source and on a
+    /// character boundary. Nodes parsed from another source can have
+    /// offsets that aren't.
+    pub fn position(&self, offset: usize) -> Option<Position> {
+        let line = self.line_starts.partition_point(|&start| start <= offset);
+        let line_start = self.line_starts[line - 1];
+        let column = self.source.get(line_start..offset)?.chars().count() + 1;
+
+        Some(Position {
+            offset,
+            line,
+            column,
+        })
+    }
+
+    pub fn source(&self) -> &'a str {
+        self.source
+    }
+
+    /// The byte offset at which the 1-based `line` starts.
+    pub fn line_start(&self, line: usize) -> usize {
+        self.line_starts[line - 1]
+    }
+}
+
+pub trait Node {
+    fn start(&self) -> usize;
+
+    fn end(&self) -> usize;
+
+    /// The position of the node in the source of `index`, if the node was
+    /// parsed from that source.
+    fn start_position(&self, index: &LineIndex) -> Option<Position> {
+        index.position(self.start())
+    }
+
+    /// The position right after the last character of the node.
+    fn end_position(&self, index: &LineIndex) -> Option<Position> {
+        index.position(self.end())
+    }
+}
+
+macro_rules! impl_node {
+    ($($ty: ty),*) => {
+        $(impl Node for $ty {
+            fn start(&self) -> usize {
+                self.start
+            }
+
+            fn end(&self) -> usize {
+                self.end
+            }
+        })*
+    };
+}
+
+impl_node!(Comment<'_>, Declaration<'_>, AtRule<'_>, Rule<'_>, Root<'_>);
+
+impl Node for BlockChild<'_> {
+    fn start(&self) -> usize {
+        match self {
+            BlockChild::Rule(rule) => rule.start,
+            BlockChild::AtRule(at_rule) => at_rule.start,
+            BlockChild::Declaration(decl) => decl.start,
+            BlockChild::Comment(comment) => comment.start,
+        }
+    }
+
+    fn end(&self) -> usize {
+        match self {
+            BlockChild::Rule(rule) => rule.end,
+            BlockChild::AtRule(at_rule) => at_rule.end,
+            BlockChild::Declaration(decl) => decl.end,
+            BlockChild::Comment(comment) => comment.end,
+        }
+    }
+}
+
+impl Node for RootChild<'_> {
+    fn start(&self) -> usize {
+        match self {
+            RootChild::Rule(rule) => rule.start,
+            RootChild::AtRule(at_rule) => at_rule.start,
+            RootChild::Comment(comment) => comment.start,
+        }
+    }
+
+    fn end(&self) -> usize {
+        match self {
+            RootChild::Rule(rule) => rule.end,
+            RootChild::AtRule(at_rule) => at_rule.end,
+            RootChild::Comment(comment) => comment.end,
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     #[test]
@@ -58,4 +397,95 @@ mod tests {
         let result = 2 + 2;
         assert_eq!(result, 4);
     }
+
+    use super::*;
+
+    #[test]
+    fn line_index_newlines() {
+        let index = LineIndex::new("a\nb\r\nc\rd\u{c}é€f");
+
+        let positions = [0, 2, 3, 5, 7, 9, 11, 14]
+            .iter()
+            .map(|&offset| {
+                let Position { line, column, .. } = index.position(offset).unwrap();
+                (line, column)
+            })
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            positions,
+            vec![
+                (1, 1),
+                (2, 1),
+                (2, 2),
+                (3, 1),
+                (4, 1),
+                (5, 1),
+                (5, 2),
+                (5, 3)
+            ]
+        );
+
+        let index = LineIndex::for_source_map("a\u{c}b\r\nc");
+        let lines = [2, 5].map(|offset| index.position(offset).unwrap().line);
+        assert_eq!(lines, [1, 2]);
+    }
+
+    #[test]
+    fn node_positions() {
+        let index = LineIndex::new("a {\n  b: c;\n}");
+        let decl = Declaration {
+            prop: "b".into(),
+            value: "c".into(),
+            important: false,
+            raws: None,
+            start: 6,
+            end: 10,
+        };
+
+        assert_eq!(
+            decl.start_position(&index),
+            Some(Position {
+                offset: 6,
+                line: 2,
+                column: 3
+            })
+        );
+        assert_eq!(
+            decl.end_position(&index),
+            Some(Position {
+                offset: 10,
+                line: 2,
+                column: 7
+            })
+        );
+
+        let index = LineIndex::new("é");
+        assert_eq!(decl.start_position(&index), None);
+        assert_eq!(index.position(1), None);
+    }
+
+    #[test]
+    fn source_map_url() {
+        let comment = |text: &'static str| {
+            RootChild::Comment(Comment {
+                text: text.into(),
+                raws: None,
+                start: 0,
+                end: 0,
+            })
+        };
+        let root = Root {
+            nodes: vec![
+                comment("# sourceMappingURL=old.map "),
+                comment("# sourceMappingURL=a.css.map "),
+                comment(" not a map "),
+            ],
+            raws: None,
+            start: 0,
+            end: 0,
+        };
+
+        assert_eq!(root.source_map_url(), Some("a.css.map"));
+    }
 }
@@ -1,8 +1,60 @@
-#[derive(Debug, PartialEq)]
-pub enum ParseError {
-    Error,
+use std::fmt;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ParseErrorKind {
+    UnexpectedToken,
+    UnexpectedEndOfInput,
+    UnclosedBlock,
+    MissingColon,
+    MissingValue,
+    UnterminatedComment,
+    BadString,
+    BadUrl,
+    NestingTooDeep,
+}
+
+impl fmt::Display for ParseErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use ParseErrorKind::*;
+        f.write_str(match self {
+            UnexpectedToken => "unexpected token",
+            UnexpectedEndOfInput => "unexpected end of input",
+            UnclosedBlock => "unclosed block",
+            MissingColon => "missing colon",
+            MissingValue => "missing value",
+            UnterminatedComment => "unterminated comment",
+            BadString => "bad string",
+            BadUrl => "bad url",
+            NestingTooDeep => "nesting too deep",
+        })
+    }
 }
 
+#[derive(Clone, Debug, PartialEq)]
+pub struct ParseError {
+    pub kind: ParseErrorKind,
+    pub start: usize,
+    pub end: usize,
+    /// 1-based line of `start`.
+    pub line: usize,
+    /// 1-based column of `start`.
+    pub column: usize,
+    pub expected: String,
+    pub found: String,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} at {}:{}: expected {}, found {}",
+            self.kind, self.line, self.column, self.expected, self.found
+        )
+    }
+}
+
+impl std::error::Error for ParseError {}
+
 #[cfg(test)]
 mod tests {
     #[test]
@@ -10,4 +62,24 @@ mod tests {
         let result = 2 + 2;
         assert_eq!(result, 4);
     }
+
+    use super::*;
+
+    #[test]
+    fn display() {
+        let err = ParseError {
+            kind: ParseErrorKind::MissingColon,
+            start: 6,
+            end: 8,
+            line: 2,
+            column: 3,
+            expected: "`:`".to_string(),
+            found: "`red`".to_string(),
+        };
+
+        assert_eq!(
+            err.to_string(),
+            "missing colon at 2:3: expected `:`, found `red`"
+        );
+    }
 }
@@ -1,7 +1,30 @@
 #[macro_export]
 macro_rules! assert_parse_ok {
     ($input: expr, $output: expr) => {
-        assert_eq!(Parser::new($input).parse(), Ok($output));
+        let mut root = Parser::new($input).parse().unwrap();
+        root.clear_raws();
+        assert_eq!(root, $output);
+    };
+}
+
+#[macro_export]
+macro_rules! assert_parse_err {
+    ($input: expr, $kind: expr, $line: expr, $column: expr) => {
+        let err = Parser::new($input).parse().unwrap_err();
+        assert_eq!((err.kind, err.line, err.column), ($kind, $line, $column));
+    };
+}
+
+#[macro_export]
+macro_rules! assert_parse_safe {
+    ($input: expr, $output: expr, $kinds: expr) => {
+        let (mut root, errors) = Parser::new($input).parse_safe();
+        root.clear_raws();
+        assert_eq!(root, $output);
+        assert_eq!(
+            errors.iter().map(|err| err.kind).collect::<Vec<_>>(),
+            $kinds
+        );
     };
 }
 
@@ -11,7 +34,8 @@ macro_rules! root_comment {
         RootChild::Comment(Comment {
             start: $start,
             end: $end,
-            text: $text.to_string(),
+            text: $text.into(),
+            raws: None,
         })
     };
 }
@@ -22,7 +46,8 @@ macro_rules! comment {
         BlockChild::Comment(Comment {
             start: $start,
             end: $end,
-            text: $text.to_string(),
+            text: $text.into(),
+            raws: None,
         })
     };
 }
@@ -34,6 +59,7 @@ macro_rules! root {
             start: $start,
             end: $end,
             nodes: $nodes,
+            raws: None,
         }
     };
 }
@@ -44,34 +70,70 @@ macro_rules! root_rule {
         RootChild::Rule(Rule {
             start: $start,
             end: $end,
-            selector: $selector.to_string(),
+            selector: $selector.into(),
+            nodes: $nodes,
+            raws: None,
+        })
+    };
+}
+
+#[macro_export]
+macro_rules! rule {
+    ($start: expr, $end: expr, $selector: expr, $nodes: expr) => {
+        BlockChild::Rule(Rule {
+            start: $start,
+            end: $end,
+            selector: $selector.into(),
             nodes: $nodes,
+            raws: None,
         })
     };
 }
 
 #[macro_export]
 macro_rules! root_at_rule {
+    ($start: expr, $end: expr, $name: expr, $params: expr) => {
+        RootChild::AtRule(AtRule {
+            start: $start,
+            end: $end,
+            name: $name.into(),
+            params: $params.into(),
+            nodes: None,
+            raws: None,
+        })
+    };
     ($start: expr, $end: expr, $name: expr, $params: expr, $nodes: expr) => {
         RootChild::AtRule(AtRule {
             start: $start,
             end: $end,
-            name: $name.to_string(),
-            params: $params.to_string(),
-            nodes: $nodes,
+            name: $name.into(),
+            params: $params.into(),
+            nodes: Some($nodes),
+            raws: None,
         })
     };
 }
 
 #[macro_export]
 macro_rules! at_rule {
+    ($start: expr, $end: expr, $name: expr, $params: expr) => {
+        BlockChild::AtRule(AtRule {
+            start: $start,
+            end: $end,
+            name: $name.into(),
+            params: $params.into(),
+            nodes: None,
+            raws: None,
+        })
+    };
     ($start: expr, $end: expr, $name: expr, $params: expr, $nodes: expr) => {
         BlockChild::AtRule(AtRule {
             start: $start,
             end: $end,
-            name: $name.to_string(),
-            params: $params.to_string(),
-            nodes: $nodes,
+            name: $name.into(),
+            params: $params.into(),
+            nodes: Some($nodes),
+            raws: None,
         })
     };
 }
@@ -79,11 +141,16 @@ macro_rules! at_rule {
 #[macro_export]
 macro_rules! decl {
     ($start: expr, $end: expr, $prop: expr, $value: expr) => {
+        $crate::decl!($start, $end, $prop, $value, false)
+    };
+    ($start: expr, $end: expr, $prop: expr, $value: expr, $important: expr) => {
         BlockChild::Declaration(Declaration {
             start: $start,
             end: $end,
-            prop: $prop.to_string(),
-            value: $value.to_string(),
+            prop: $prop.into(),
+            value: $value.into(),
+            important: $important,
+            raws: None,
         })
     };
 }
@@ -1,223 +1,820 @@
 use ast::*;
-use error::ParseError;
-use std::iter::Peekable;
+use error::{ParseError, ParseErrorKind};
+use std::borrow::Cow;
+use std::cell::{Cell, OnceCell};
+use std::collections::VecDeque;
 use tokenizer::{Token, TokenKind, Tokenizer};
 
+/// How many blocks can be nested by default, see `Parser::with_max_depth`.
+pub const DEFAULT_MAX_DEPTH: usize = 128;
+
 pub struct Parser<'a> {
-    tokenizer: Peekable<Tokenizer<'a>>,
+    tokenizer: Lookahead<'a>,
     source: &'a str,
     pos: usize,
+    unterminated_comment: Option<Token>,
+    /// Whether syntax errors are recorded in `errors` and recovered from
+    /// instead of failing the parse.
+    recover: bool,
+    errors: Vec<ParseError>,
+    /// Built on the first error, to locate errors.
+    line_index: OnceCell<LineIndex<'a>>,
+    /// The last position located, so that columns of errors on a long line
+    /// are counted from the previous error instead of the line start.
+    last_position: Cell<Option<Position>>,
+    /// The number of blocks the parser is in.
+    depth: usize,
+    max_depth: usize,
 }
 
 impl<'a> Parser<'a> {
     pub fn new(input: &'a str) -> Self {
         Parser {
-            tokenizer: Tokenizer::new(input).peekable(),
+            tokenizer: Lookahead {
+                tokenizer: Tokenizer::new(input),
+                buffer: VecDeque::new(),
+            },
             source: input,
             pos: 0,
+            unterminated_comment: None,
+            recover: false,
+            errors: vec![],
+            line_index: OnceCell::new(),
+            last_position: Cell::new(None),
+            depth: 0,
+            max_depth: DEFAULT_MAX_DEPTH,
+        }
+    }
+
+    /// Sets how many rules and at-rules can be nested in one another. Deeper
+    /// blocks are reported as errors, which keeps hostile input from
+    /// exhausting the stack.
+    pub fn with_max_depth(mut self, max_depth: usize) -> Self {
+        self.max_depth = max_depth;
+        self
+    }
+
+    /// Parses the whole input without ever failing. Syntax errors are handled
+    /// as the CSS Syntax spec does: a bad declaration is dropped up to the
+    /// next `;`, a bad rule up to the end of its block, and blocks left open
+    /// are closed at the end of input. The errors are returned along with
+    /// the tree.
+    pub fn parse_safe(&mut self) -> (Root<'a>, Vec<ParseError>) {
+        self.recover = true;
+        let root = self.parse().unwrap_or_else(|err| {
+            self.errors.push(err);
+            Root {
+                nodes: vec![],
+                raws: None,
+                start: 0,
+                end: self.source.len(),
+            }
+        });
+
+        (root, std::mem::take(&mut self.errors))
+    }
+
+    /// Parses a single rule such as `a { color: red }`, which must make the
+    /// whole input but for whitespace and comments.
+    pub fn parse_rule(&mut self) -> Result<Rule<'a>, ParseError> {
+        let before = self.parse_fragment_start();
+        if let Some(token @ Token(TokenKind::AtKeyword, ..)) = self.tokenizer.peek().copied() {
+            return Err(self.error(ParseErrorKind::UnexpectedToken, Some(&token), "selector"));
+        }
+
+        let rule = self.consume_rule(before)?;
+        self.parse_end(rule)
+    }
+
+    /// Parses a single at-rule such as `@media print { a { color: red } }`
+    /// or `@import "a.css";`, which must make the whole input but for
+    /// whitespace and comments.
+    pub fn parse_at_rule(&mut self) -> Result<AtRule<'a>, ParseError> {
+        let before = self.parse_fragment_start();
+        let at_rule = self.consume_at_rule(before)?;
+        self.parse_end(at_rule)
+    }
+
+    /// Parses a single declaration such as `color: red;`, which must make the
+    /// whole input but for whitespace and comments.
+    pub fn parse_declaration(&mut self) -> Result<Declaration<'a>, ParseError> {
+        let before = self.parse_fragment_start();
+        match self.tokenizer.peek().copied() {
+            Some(Token(TokenKind::Ident, ..)) => {}
+            token => {
+                return Err(self.error(ParseErrorKind::UnexpectedToken, token.as_ref(), "property"))
+            }
         }
+
+        let decl = self.consume_declaration(before)?;
+        self.parse_end(decl)
     }
 
-    pub fn parse(&mut self) -> Result<Root, ParseError> {
+    /// Parses the whole input as the value of a declaration, such as
+    /// `0 auto`, and returns it without the whitespace and comments around.
+    pub fn parse_value(&mut self) -> Result<Cow<'a, str>, ParseError> {
         use TokenKind::*;
-        let mut nodes: Vec<RootChild> = vec![];
+        self.skip_while(|t| matches!(t, Some(Token(Space, ..)) | Some(Token(Comment, ..))));
+
         let start = self.pos;
+        let tokens = self.consume_value(false)?;
+        let end = match tokens.iter().rev().find(|token| token.0 != Comment) {
+            Some(token) => token.2,
+            None => {
+                let token = self.tokenizer.peek().copied();
+                return Err(self.error(ParseErrorKind::MissingValue, token.as_ref(), "value"));
+            }
+        };
+
+        self.parse_end(self.source[start..end].into())
+    }
 
-        while let Some(token) = self.tokenizer.peek() {
-            match token {
-                Token(Space, ..) => self.skip_while(|t| matches!(t, Some(Token(Space, ..)))),
-                Token(At, ..) => nodes.push(RootChild::AtRule(self.parse_at_rule()?)),
-                Token(Word, ..) => nodes.push(RootChild::Rule(self.parse_rule()?)),
-                Token(Comment, ..) => nodes.push(RootChild::Comment(self.parse_comment()?)),
-                _ => {
+    /// Skips the whitespace and comments before a fragment and returns the
+    /// whitespace that follows the last comment.
+    fn parse_fragment_start(&mut self) -> &'a str {
+        loop {
+            let before = self.parse_spaces();
+            match self.tokenizer.peek() {
+                Some(Token(TokenKind::Comment, ..)) => {
                     self.next_token();
                 }
+                _ => return before,
             }
         }
+    }
 
-        let end = self.pos;
-        Ok(Root { nodes, start, end })
+    /// Checks that only whitespace and comments are left after the fragment
+    /// `node`.
+    fn parse_end<T>(&mut self, node: T) -> Result<T, ParseError> {
+        use TokenKind::*;
+        self.skip_while(|t| matches!(t, Some(Token(Space, ..)) | Some(Token(Comment, ..))));
+        match self.tokenizer.peek().copied() {
+            None if self.unterminated_comment.is_none() => Ok(node),
+            token => Err(self.error(
+                ParseErrorKind::UnexpectedToken,
+                token.as_ref(),
+                "end of input",
+            )),
+        }
     }
 
-    fn parse_comment(&mut self) -> Result<Comment, ParseError> {
-        let start = self.pos;
+    /// Parses the contents of a block without the braces around it, such as
+    /// the `color: red; margin: 0 auto` of a `style` attribute.
+    pub fn parse_declaration_list(&mut self) -> Result<Vec<BlockChild<'a>>, ParseError> {
+        let mut nodes = vec![];
 
-        let text = if let Some(token) = self.next_token() {
-            let Token(_, start, end) = token;
-            &self.source[start + 2..end - 1]
-        } else {
-            return Err(ParseError::Error);
+        loop {
+            let (children, _) = self.parse_declartion_or_at_rule_list()?;
+            nodes.extend(children);
+
+            // only a stray `}` stops the list before the end of input
+            match self.tokenizer.peek().copied() {
+                None => break,
+                token => {
+                    let err = self.error(
+                        ParseErrorKind::UnexpectedToken,
+                        token.as_ref(),
+                        "declaration",
+                    );
+                    self.recover::<Declaration>(Err(err), true, true)?;
+                    self.next_token();
+                }
+            }
         }
-        .to_string();
 
-        let end = self.pos;
+        if self.unterminated_comment.is_some() {
+            let err = self.error(ParseErrorKind::UnterminatedComment, None, "`*/`");
+            self.recover::<Declaration>(Err(err), true, true)?;
+        }
+
+        Ok(nodes)
+    }
+
+    /// Parses a declaration list like `parse_declaration_list`, recovering
+    /// from syntax errors like `parse_safe`.
+    pub fn parse_declaration_list_safe(&mut self) -> (Vec<BlockChild<'a>>, Vec<ParseError>) {
+        self.recover = true;
+        let nodes = self.parse_declaration_list().unwrap_or_else(|err| {
+            self.errors.push(err);
+            vec![]
+        });
 
-        Ok(Comment { text, start, end })
+        (nodes, std::mem::take(&mut self.errors))
     }
 
-    fn parse_declaration(&mut self) -> Result<Declaration, ParseError> {
+    pub fn parse(&mut self) -> Result<Root<'a>, ParseError> {
         use TokenKind::*;
-        let decl_start;
-        let prop = if let Some(token) = self.next_token() {
-            let Token(_, start, end) = token;
-            decl_start = start;
+        let mut nodes: Vec<RootChild> = vec![];
+        let start = self.pos;
 
-            &self.source[start..end + 1]
-        } else {
-            return Err(ParseError::Error);
+        let after = loop {
+            // `<!--` and `-->` are ignored at the top level, like whitespace
+            let before_start = self.pos;
+            self.skip_while(|t| matches!(t, Some(Token(Space | Cdo | Cdc, ..))));
+            let before = &self.source[before_start..self.pos];
+
+            match self.tokenizer.peek() {
+                Some(Token(AtKeyword | Delim('@'), ..)) => {
+                    let at_rule = self.consume_at_rule(before);
+                    nodes.extend(self.recover(at_rule, true, false)?.map(RootChild::AtRule));
+                }
+                Some(Token(Comment, ..)) => {
+                    let comment = self.parse_comment(before);
+                    nodes.extend(self.recover(comment, false, false)?.map(RootChild::Comment));
+                }
+                None => break before,
+                Some(Token(OpenCurly | ClosedCurly | Semicolon, ..)) => {
+                    let token = self.tokenizer.peek().copied();
+                    let err = self.error(
+                        ParseErrorKind::UnexpectedToken,
+                        token.as_ref(),
+                        "rule or at-rule",
+                    );
+                    self.recover::<Rule>(Err(err), false, false)?;
+                }
+                Some(_) => {
+                    let rule = self.consume_rule(before);
+                    nodes.extend(self.recover(rule, false, false)?.map(RootChild::Rule));
+                }
+            }
+        };
+
+        if self.unterminated_comment.is_some() {
+            let err = self.error(ParseErrorKind::UnterminatedComment, None, "`*/`");
+            self.recover::<Root>(Err(err), false, false)?;
         }
-        .to_string();
 
-        self.skip_while(|t| matches!(t, Some(Token(Space, ..)) | Some(Token(Comment, ..))));
+        let end = self.pos;
+        Ok(Root {
+            nodes,
+            raws: Some(RootRaws {
+                after: after.into(),
+            }),
+            start,
+            end,
+        })
+    }
+
+    fn parse_comment(&mut self, before: &'a str) -> Result<Comment<'a>, ParseError> {
+        let token = self.next_token();
+        if self.unterminated_comment.is_some() {
+            return Err(self.error(ParseErrorKind::UnterminatedComment, None, "`*/`"));
+        }
 
-        if let Some(Token(Colon, ..)) = self.next_token() {
-            self.skip_while(|t| matches!(t, Some(Token(Space, ..)) | Some(Token(Comment, ..))));
+        let (text, start, end) = if let Some(Token(_, start, end)) = token {
+            (&self.source[start + 2..end - 2], start, end)
         } else {
-            return Err(ParseError::Error);
+            return Err(self.error(ParseErrorKind::UnexpectedEndOfInput, None, "comment"));
+        };
+
+        Ok(Comment {
+            text: text.into(),
+            raws: Some(CommentRaws {
+                before: before.into(),
+            }),
+            start,
+            end,
+        })
+    }
+
+    /// Parses a declaration along with the `;` terminating it, if any.
+    fn consume_declaration(&mut self, before: &'a str) -> Result<Declaration<'a>, ParseError> {
+        use TokenKind::*;
+        let (start, prop_end) = match self.next_token() {
+            Some(Token(_, start, end)) => (start, end),
+            None => return Err(self.error(ParseErrorKind::UnexpectedEndOfInput, None, "property")),
+        };
+        let prop = &self.source[start..prop_end];
+        // the value of a custom property is kept verbatim, comments included,
+        // and can be empty or hold `{}` blocks
+        let custom = prop.starts_with("--");
+
+        self.skip_while(|t| matches!(t, Some(Token(Space, ..)) | Some(Token(Comment, ..))));
+
+        // the token found instead of the colon is left to error recovery
+        match self.tokenizer.peek().copied() {
+            Some(Token(Colon, ..)) => {
+                self.next_token();
+                self.skip_while(|t| match t {
+                    Some(Token(Space, ..)) => true,
+                    Some(Token(Comment, ..)) => !custom,
+                    _ => false,
+                })
+            }
+            token => return Err(self.error(ParseErrorKind::MissingColon, token.as_ref(), "`:`")),
         }
 
-        let value = if let Some(token) = self.next_token() {
-            let Token(_, start, end) = token;
+        let value_start = self.pos;
+        let tokens = self.consume_value(custom)?;
 
-            &self.source[start..end + 1]
-        } else {
-            return Err(ParseError::Error);
+        let raw_end = tokens.last().map_or(value_start, |token| token.2);
+        let important = self.find_important(&tokens);
+        // the value stops at the `!` of `!important`, which can be in the
+        // middle of a token as in `red!important`
+        let bang = important.map_or(raw_end, |(bang, _)| bang);
+        let value_ends = |comments: bool| {
+            tokens
+                .iter()
+                .filter(|token| token.1 < bang && (comments || token.0 != Comment))
+                .map(|token| token.2.min(bang))
+                .max()
+        };
+
+        let value_end = match value_ends(custom) {
+            Some(end) => end,
+            None if custom => value_start,
+            None => {
+                let token = self.tokenizer.peek().copied();
+                return Err(self.error(ParseErrorKind::MissingValue, token.as_ref(), "value"));
+            }
+        };
+        let raw_value_end = value_ends(true).unwrap_or(value_end);
+        let value = &self.source[value_start..value_end];
+        let after = &self.source[raw_end..self.pos];
+
+        let semicolon = matches!(self.tokenizer.peek(), Some(Token(Semicolon, ..)));
+        if semicolon {
+            self.next_token();
         }
-        .to_string();
 
-        let end = self.pos;
         Ok(Declaration {
-            prop,
-            value,
-            start: decl_start,
-            end,
+            prop: prop.into(),
+            important: important.is_some(),
+            raws: Some(DeclarationRaws {
+                before: before.into(),
+                between: self.source[prop_end..value_start].into(),
+                value: self.raw(&value.into(), value_start, raw_value_end),
+                important: important.map(|_| self.source[raw_value_end..raw_end].into()),
+                after: after.into(),
+                semicolon,
+            }),
+            value: value.into(),
+            start,
+            end: important.map_or(value_end, |(_, end)| end),
         })
     }
 
-    fn parse_rule(&mut self) -> Result<Rule, ParseError> {
+    /// Consumes a value up to the terminating `;` or `}` and returns its
+    /// tokens but the whitespace. A `{` outside of brackets can only be part
+    /// of a `custom` property value.
+    fn consume_value(&mut self, custom: bool) -> Result<Vec<Token>, ParseError> {
         use TokenKind::*;
+        let mut tokens = vec![];
+        let mut brackets = Brackets::default();
 
-        let start = if let Some(Token(Word, start, _)) = self.next_token() {
-            start
-        } else {
-            return Err(ParseError::Error);
+        loop {
+            match self.tokenizer.peek() {
+                Some(Token(Semicolon | ClosedCurly, ..)) if brackets.is_empty() => break,
+                None => break,
+                Some(Token(OpenCurly, ..)) if brackets.is_empty() && !custom => {
+                    let token = self.tokenizer.peek().copied();
+                    return Err(self.error(
+                        ParseErrorKind::UnexpectedToken,
+                        token.as_ref(),
+                        "`;` or `}`",
+                    ));
+                }
+                Some(Token(BadString, ..)) => {
+                    let token = self.tokenizer.peek().copied();
+                    return Err(self.error(ParseErrorKind::BadString, token.as_ref(), "string"));
+                }
+                Some(Token(BadUrl, ..)) => {
+                    let token = self.tokenizer.peek().copied();
+                    return Err(self.error(ParseErrorKind::BadUrl, token.as_ref(), "url"));
+                }
+                Some(Token(Space, ..)) => {
+                    self.next_token();
+                }
+                Some(Token(kind, ..)) => {
+                    brackets.update(kind);
+                    tokens.extend(self.next_token());
+                }
+            }
+        }
+
+        Ok(tokens)
+    }
+
+    /// Finds a trailing `!important` among the tokens of a value and returns
+    /// the offsets of its `!` and of its end. Comments can appear between
+    /// `!` and `important`, which is case-insensitive.
+    fn find_important(&self, tokens: &[Token]) -> Option<(usize, usize)> {
+        use TokenKind::*;
+        let mut tokens = tokens.iter().rev().filter(|token| token.0 != Comment);
+
+        let flag @ Token(Ident, _, end) = tokens.next()? else {
+            return None;
         };
+        if !flag.value(self.source).eq_ignore_ascii_case("important") {
+            return None;
+        }
+
+        match tokens.next()? {
+            Token(Delim('!'), bang, _) => Some((*bang, *end)),
+            _ => None,
+        }
+    }
 
-        self.skip_while(|t| !matches!(t, Some(Token(OpenCurly, ..))));
+    fn consume_rule(&mut self, before: &'a str) -> Result<Rule<'a>, ParseError> {
+        let token = self.tokenizer.peek().copied();
+        let start = token.as_ref().map_or(self.pos, |token| token.1);
 
-        let selector = self.source[start..self.pos + 1]
-            .split("/*")
-            .next()
-            .expect("cannot filter comments on rule name")
-            .trim()
-            .to_string();
+        let (selector, end, raw_end) = self.parse_prelude(start)?;
+        if end == start {
+            return Err(self.error(ParseErrorKind::UnexpectedToken, token.as_ref(), "selector"));
+        }
+        let raw_selector = self.raw(&selector, start, raw_end);
+        let between = &self.source[raw_end..self.pos];
+        let (nodes, after) = self.parse_block()?;
 
         Ok(Rule {
             selector,
-            nodes: self.parse_block()?,
+            nodes,
+            raws: Some(RuleRaws {
+                before: before.into(),
+                selector: raw_selector,
+                between: between.into(),
+                after: after.into(),
+            }),
             start,
             end: self.pos,
         })
     }
 
-    fn parse_at_rule(&mut self) -> Result<AtRule, ParseError> {
+    fn consume_at_rule(&mut self, before: &'a str) -> Result<AtRule<'a>, ParseError> {
         use TokenKind::*;
-        self.next_token(); // skip @
-        let start = self.pos;
-
-        let name = if let Some(Token(Word, start, end)) = self.next_token() {
-            &self.source[start..end + 1]
-        } else {
-            return Err(ParseError::Error);
-        }
-        .to_string();
+        let (start, name) = match self.next_token() {
+            Some(Token(AtKeyword, start, end)) => (start, &self.source[start + 1..end]),
+            // an `@` that no name follows
+            Some(Token(Delim('@'), ..)) => {
+                let token = self.tokenizer.peek().copied();
+                return Err(self.error(
+                    ParseErrorKind::UnexpectedToken,
+                    token.as_ref(),
+                    "at-rule name",
+                ));
+            }
+            token => {
+                return Err(self.error(ParseErrorKind::UnexpectedToken, token.as_ref(), "`@`"))
+            }
+        };
+        let name_end = self.pos;
 
         self.skip_while(|t| matches!(t, Some(Token(Comment, ..)) | Some(Token(Space, ..))));
 
-        let start_params = self.pos + 1;
-
-        self.skip_while(|t| !matches!(t, Some(Token(OpenCurly, ..))));
+        let start_params = self.pos;
+        let (params, prelude_end, raw_end) = self.parse_prelude(start_params)?;
+        let mut raws = AtRuleRaws {
+            before: before.into(),
+            after_name: self.source[name_end..start_params].into(),
+            params: self.raw(&params, start_params, raw_end),
+            between: self.source[raw_end..self.pos].into(),
+            ..Default::default()
+        };
 
-        let params = self.source[start_params..self.pos + 1]
-            .split("/*")
-            .next()
-            .expect("cannot filter comments on at_rule params")
-            .trim()
-            .to_string();
+        // statements such as `@import "x.css";` end at the semicolon, the
+        // closing curly of the parent block or the end of input
+        let (nodes, end) = match self.tokenizer.peek() {
+            Some(Token(OpenCurly, ..)) => {
+                let (nodes, after) = self.parse_block()?;
+                raws.after = after.into();
+                (Some(nodes), self.pos)
+            }
+            Some(Token(Semicolon, ..)) => {
+                self.next_token();
+                raws.semicolon = true;
+                (None, self.pos)
+            }
+            _ => (None, prelude_end.max(name_end)),
+        };
 
         Ok(AtRule {
-            name,
+            name: name.into(),
             params,
-            nodes: self.parse_block()?,
+            nodes,
+            raws: Some(raws),
             start,
-            end: self.pos,
+            end,
         })
     }
 
-    fn parse_declartion_or_at_rule_list(&mut self) -> Result<Vec<BlockChild>, ParseError> {
+    /// Consumes a selector or at-rule prelude up to the opening `{` (or the
+    /// `;` or `}` ending a statement) outside of brackets and returns its
+    /// text, the end of that text and the end of its raw text. Strings are
+    /// opaque, and the comments are stripped from the text while the raw
+    /// text keeps all of them.
+    fn parse_prelude(&mut self, start: usize) -> Result<(Cow<'a, str>, usize, usize), ParseError> {
+        use TokenKind::*;
+        let mut end = start;
+        let mut raw_end = start;
+        let mut comments = vec![];
+        let mut brackets = Brackets::default();
+
+        loop {
+            match self.tokenizer.peek() {
+                Some(Token(OpenCurly | Semicolon | ClosedCurly, ..)) if brackets.is_empty() => {
+                    break
+                }
+                None => break,
+                Some(Token(BadString, ..)) => {
+                    let token = self.tokenizer.peek().copied();
+                    return Err(self.error(ParseErrorKind::BadString, token.as_ref(), "string"));
+                }
+                Some(Token(BadUrl, ..)) => {
+                    let token = self.tokenizer.peek().copied();
+                    return Err(self.error(ParseErrorKind::BadUrl, token.as_ref(), "url"));
+                }
+                Some(&Token(Comment, comment_start, comment_end)) => {
+                    comments.push(comment_start..comment_end);
+                    self.next_token();
+                    raw_end = self.pos;
+                }
+                Some(Token(Space, ..)) => {
+                    self.next_token();
+                }
+                Some(Token(kind, ..)) => {
+                    brackets.update(kind);
+                    self.next_token();
+                    raw_end = self.pos;
+                    end = self.pos;
+                }
+            }
+        }
+
+        // the text keeps what surrounds the comments, as in `a , b` for
+        // `a /* x */, b`
+        comments.retain(|comment| comment.end <= end);
+        if comments.is_empty() {
+            return Ok((self.source[start..end].into(), end, raw_end));
+        }
+        let mut text = self.source[start..comments[0].start].to_string();
+        let next_starts = comments[1..].iter().map(|comment| comment.start);
+        for (comment, next) in comments.iter().zip(next_starts.chain([end])) {
+            text.push_str(&self.source[comment.end..next]);
+        }
+
+        Ok((text.into(), end, raw_end))
+    }
+
+    /// Parses the children of a block and returns them with the whitespace
+    /// that follows the last one.
+    fn parse_declartion_or_at_rule_list(
+        &mut self,
+    ) -> Result<(Vec<BlockChild<'a>>, &'a str), ParseError> {
         use TokenKind::*;
         let mut nodes: Vec<BlockChild> = vec![];
 
-        self.skip_while(|t| matches!(t, Some(Token(Space, ..))));
-
-        while let Some(token) = self.tokenizer.peek() {
-            match token {
-                Token(Word, ..) => {
-                    nodes.push(BlockChild::Declaration(self.parse_declaration()?));
-                    self.skip_while(|t| {
-                        matches!(t, Some(Token(Space, ..)) | Some(Token(Comment, ..)))
-                    });
-
-                    match self.tokenizer.peek() {
-                        Some(Token(Semicolon, ..)) => {
-                            self.next_token();
-                            nodes.extend(self.parse_declartion_or_at_rule_list()?)
-                        }
-                        Some(Token(ClosedCurly, ..)) => break,
-                        _ => return Err(ParseError::Error),
+        loop {
+            // empty declarations are kept with the whitespace around them
+            let start = self.pos;
+            self.skip_while(|t| matches!(t, Some(Token(Space | Semicolon, ..))));
+            let before = &self.source[start..self.pos];
+
+            match self.tokenizer.peek().copied() {
+                Some(Token(AtKeyword | Delim('@'), ..)) => {
+                    let at_rule = self.consume_at_rule(before);
+                    nodes.extend(self.recover(at_rule, true, true)?.map(BlockChild::AtRule));
+                }
+                Some(Token(Comment, ..)) => {
+                    let comment = self.parse_comment(before);
+                    nodes.extend(self.recover(comment, false, true)?.map(BlockChild::Comment));
+                }
+                Some(Token(ClosedCurly, ..)) | None => return Ok((nodes, before)),
+                Some(Token(kind, ..)) if kind != OpenCurly && self.is_nested_rule() => {
+                    let rule = self.consume_rule(before);
+                    nodes.extend(self.recover(rule, false, true)?.map(BlockChild::Rule));
+                }
+                Some(Token(Ident, ..)) => {
+                    let decl = self.consume_declaration(before);
+                    let Some(decl) = self.recover(decl, true, true)? else {
+                        continue;
+                    };
+                    let semicolon = decl.raws.as_ref().is_some_and(|raws| raws.semicolon);
+                    nodes.push(BlockChild::Declaration(decl));
+
+                    // without a `;` only the end of the block can follow
+                    if !semicolon {
+                        return Ok((nodes, ""));
                     }
                 }
-                Token(At, ..) => {
-                    nodes.push(BlockChild::AtRule(self.parse_at_rule()?));
-                    self.skip_while(|t| {
-                        matches!(t, Some(Token(Space, ..)) | Some(Token(Comment, ..)))
-                    });
-                    nodes.extend(self.parse_declartion_or_at_rule_list()?);
+                Some(token) if self.recover => {
+                    let err =
+                        self.error(ParseErrorKind::UnexpectedToken, Some(&token), "declaration");
+                    self.recover::<Declaration>(Err(err), true, true)?;
                 }
-                Token(Comment, ..) => nodes.push(BlockChild::Comment(self.parse_comment()?)),
-                _ => break,
+                _ => return Ok((nodes, before)),
             }
         }
+    }
+
+    /// Looks ahead to tell a nested rule such as `&:hover { ... }` apart from
+    /// a declaration: only a rule reaches a `{` before any `;` or `}` outside
+    /// of brackets. Custom properties such as `--x: { ... }` are never rules.
+    fn is_nested_rule(&mut self) -> bool {
+        use TokenKind::*;
 
-        self.skip_while(|t| matches!(t, Some(Token(Space, ..))));
+        let mut brackets = Brackets::default();
+        let mut custom_property = false;
+        let mut significant = 0;
 
-        Ok(nodes)
+        for n in 0.. {
+            let Some(&token) = self.tokenizer.nth(n) else {
+                break;
+            };
+            if !matches!(token.0, Space | Comment) {
+                match (significant, token.0) {
+                    (0, Ident) => custom_property = token.raw(self.source).starts_with("--"),
+                    (1, Colon) if custom_property => return false,
+                    _ => {}
+                }
+                significant += 1;
+            }
+
+            match token.0 {
+                OpenCurly if brackets.is_empty() => return true,
+                Semicolon | ClosedCurly if brackets.is_empty() => return false,
+                kind => brackets.update(&kind),
+            }
+        }
+
+        false
     }
 
-    fn parse_block(&mut self) -> Result<Vec<BlockChild>, ParseError> {
+    fn parse_block(&mut self) -> Result<(Vec<BlockChild<'a>>, &'a str), ParseError> {
         use TokenKind::*;
 
-        if let Some(Token(OpenCurly, ..)) = self.next_token() {
-        } else {
-            return Err(ParseError::Error);
+        let open = match self.tokenizer.peek().copied() {
+            Some(token @ Token(OpenCurly, ..)) => token,
+            token => {
+                return Err(self.error(ParseErrorKind::UnexpectedToken, token.as_ref(), "`{`"))
+            }
+        };
+        if self.depth == self.max_depth {
+            let expected = format!("at most {} nested blocks", self.max_depth);
+            return Err(self.error(ParseErrorKind::NestingTooDeep, Some(&open), &expected));
         }
+        self.next_token();
 
-        let nodes = self.parse_declartion_or_at_rule_list()?;
+        self.depth += 1;
+        let list = self.parse_declartion_or_at_rule_list();
+        self.depth -= 1;
+        let (nodes, after) = list?;
 
-        if let Some(Token(ClosedCurly, ..)) = self.next_token() {
-        } else {
-            return Err(ParseError::Error);
+        match self.next_token() {
+            Some(Token(ClosedCurly, ..)) => Ok((nodes, after)),
+            None => {
+                let mut err = self.error(ParseErrorKind::UnclosedBlock, None, "`}`");
+                if err.kind == ParseErrorKind::UnclosedBlock {
+                    // point at the `{` that was never closed
+                    let position = self.position(open.1);
+                    err.start = open.1;
+                    err.line = position.line;
+                    err.column = position.column;
+                }
+                // the block is closed by the end of input
+                self.recover::<()>(Err(err), false, true)?;
+                Ok((nodes, after))
+            }
+            token => Err(self.error(ParseErrorKind::UnexpectedToken, token.as_ref(), "`}`")),
         }
-
-        Ok(nodes)
     }
 
     fn next_token(&mut self) -> Option<Token> {
         let token = self.tokenizer.next()?;
         self.pos = token.2;
+
+        if let Token(TokenKind::Comment, start, end) = token {
+            if self.unterminated_comment.is_none()
+                && (end - start < 4 || !self.source[..end].ends_with("*/"))
+            {
+                self.unterminated_comment = Some(token);
+            }
+        }
+
         Some(token)
     }
 
+    /// Passes `result` through, unless in recovery mode where an error is
+    /// recorded and the rest of the node that failed is skipped. A
+    /// `statement` ends at the first `;` outside of blocks, any node ends at
+    /// the end of its block and a `nested` one also at the `}` closing its
+    /// parent.
+    fn recover<T>(
+        &mut self,
+        result: Result<T, ParseError>,
+        statement: bool,
+        nested: bool,
+    ) -> Result<Option<T>, ParseError> {
+        use TokenKind::*;
+        let err = match result {
+            Ok(node) => return Ok(Some(node)),
+            Err(err) if !self.recover => return Err(err),
+            Err(err) => err,
+        };
+
+        // an unterminated comment is reported by every node up to the root
+        let reported = err.kind == ParseErrorKind::UnterminatedComment
+            && self.errors.last().is_some_and(|last| last.kind == err.kind);
+        if !reported {
+            self.errors.push(err);
+        }
+
+        let mut brackets = Brackets::default();
+        loop {
+            match self.tokenizer.peek() {
+                None => break,
+                Some(Token(ClosedCurly, ..)) if brackets.is_empty() && nested => break,
+                Some(Token(Semicolon, ..)) if brackets.is_empty() && statement => {
+                    self.next_token();
+                    break;
+                }
+                Some(Token(ClosedCurly, ..)) if brackets.0 == [ClosedCurly] => {
+                    self.next_token();
+                    break;
+                }
+                Some(Token(kind, ..)) => brackets.update(kind),
+            }
+            self.next_token();
+        }
+
+        Ok(None)
+    }
+
+    /// Builds an error of `kind` at `token`, or at the end of input when
+    /// there is no token. An unterminated comment swallows the rest of the
+    /// input, so once one was seen it is reported instead.
+    fn error(&self, kind: ParseErrorKind, token: Option<&Token>, expected: &str) -> ParseError {
+        let len = self.source.len();
+
+        let (kind, token, expected) = match &self.unterminated_comment {
+            Some(comment) => (ParseErrorKind::UnterminatedComment, Some(comment), "`*/`"),
+            None => (kind, token, expected),
+        };
+
+        let (start, end, found) = match token {
+            Some(Token(TokenKind::Comment, start, _))
+                if kind == ParseErrorKind::UnterminatedComment =>
+            {
+                (*start, len, "end of input".to_string())
+            }
+            Some(Token(_, start, end)) => {
+                (*start, *end, format!("`{}`", &self.source[*start..*end]))
+            }
+            None => (len, len, "end of input".to_string()),
+        };
+
+        let position = self.position(start);
+
+        ParseError {
+            kind,
+            start,
+            end,
+            line: position.line,
+            column: position.column,
+            expected: expected.to_string(),
+            found,
+        }
+    }
+
+    fn position(&self, offset: usize) -> Position {
+        let index = self.line_index.get_or_init(|| LineIndex::new(self.source));
+
+        let since_last = self
+            .last_position
+            .get()
+            .filter(|last| last.offset <= offset)
+            .map(|last| (last, &self.source[last.offset..offset]))
+            .filter(|(_, text)| !text.contains(['\n', '\r', '\x0c']));
+        let position = match since_last {
+            Some((last, text)) => Position {
+                offset,
+                line: last.line,
+                column: last.column + text.chars().count(),
+            },
+            None => index
+                .position(offset)
+                .expect("parser offsets are in the source"),
+        };
+        self.last_position.set(Some(position));
+
+        position
+    }
+
+    /// The raw text `start..end` when it differs from the parsed `value`.
+    fn raw(&self, value: &Cow<'a, str>, start: usize, end: usize) -> Option<Raw<'a>> {
+        let raw = &self.source[start..end];
+        (raw != value).then(|| Raw {
+            value: value.clone(),
+            raw: raw.into(),
+        })
+    }
+
+    fn parse_spaces(&mut self) -> &'a str {
+        let start = self.pos;
+        self.skip_while(|t| matches!(t, Some(Token(TokenKind::Space, ..))));
+        &self.source[start..self.pos]
+    }
+
     fn skip_while<F>(&mut self, condition: F)
     where
         F: Fn(Option<&Token>) -> bool,
@@ -230,6 +827,56 @@ impl<'a> Parser<'a> {
     }
 }
 
+/// The tokens ahead of the parser. The ones looked at with `nth` are kept
+/// until consumed, so that looking ahead doesn't tokenize the input twice.
+struct Lookahead<'a> {
+    tokenizer: Tokenizer<'a>,
+    buffer: VecDeque<Token>,
+}
+
+impl Lookahead<'_> {
+    fn peek(&mut self) -> Option<&Token> {
+        self.nth(0)
+    }
+
+    fn nth(&mut self, n: usize) -> Option<&Token> {
+        while self.buffer.len() <= n {
+            self.buffer.push_back(self.tokenizer.next()?);
+        }
+        self.buffer.get(n)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        self.buffer.pop_front().or_else(|| self.tokenizer.next())
+    }
+}
+
+/// The closing brackets expected for the brackets opened so far while
+/// scanning tokens. Within brackets `;`, `{` and `}` are not structure.
+#[derive(Default)]
+struct Brackets(Vec<TokenKind>);
+
+impl Brackets {
+    fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Opens a bracket for `(`, `[`, `{` or a function, and closes the last
+    /// one when `kind` matches it. Other closing brackets are ignored.
+    fn update(&mut self, kind: &TokenKind) {
+        use TokenKind::*;
+        match kind {
+            OpenParen | Function => self.0.push(ClosedParen),
+            OpenSquare => self.0.push(ClosedSquare),
+            OpenCurly => self.0.push(ClosedCurly),
+            kind if self.0.last() == Some(kind) => {
+                self.0.pop();
+            }
+            _ => {}
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -244,13 +891,13 @@ mod tests {
     fn parse_root_level_comment() {
         assert_parse_ok!(
             "/* hello */",
-            root!(0, 10, vec![root_comment!(0, 10, " hello ")])
+            root!(0, 11, vec![root_comment!(0, 11, " hello ")])
         );
     }
 
     #[test]
     fn parse_empty_rule() {
-        assert_parse_ok!("foo {}", root!(0, 5, vec![root_rule!(0, 5, "foo", vec![])]));
+        assert_parse_ok!("foo {}", root!(0, 6, vec![root_rule!(0, 6, "foo", vec![])]));
     }
 
     #[test]
@@ -259,15 +906,15 @@ mod tests {
             "foo {a:b; /* hello */ c:b}",
             root!(
                 0,
-                25,
+                26,
                 vec![root_rule!(
                     0,
-                    25,
+                    26,
                     "foo",
                     vec![
-                        decl!(5, 7, "a", "b"),
-                        comment!(9, 20, " hello "),
-                        decl!(22, 24, "c", "b")
+                        decl!(5, 8, "a", "b"),
+                        comment!(10, 21, " hello "),
+                        decl!(22, 25, "c", "b")
                     ]
                 )]
             )
@@ -280,8 +927,8 @@ mod tests {
             "foo { a: b }",
             root!(
                 0,
-                11,
-                vec![root_rule!(0, 11, "foo", vec![decl!(6, 9, "a", "b")])]
+                12,
+                vec![root_rule!(0, 12, "foo", vec![decl!(6, 10, "a", "b")])]
             )
         );
     }
@@ -290,7 +937,7 @@ mod tests {
     fn parse_empty_at_rule() {
         assert_parse_ok!(
             "@foo {}",
-            root!(0, 6, vec![root_at_rule!(0, 6, "foo", "", vec![])])
+            root!(0, 7, vec![root_at_rule!(0, 7, "foo", "", vec![])])
         );
     }
 
@@ -298,7 +945,7 @@ mod tests {
     fn parse_empty_at_rule_with_params() {
         assert_parse_ok!(
             "@foo (bar) {}",
-            root!(0, 12, vec![root_at_rule!(0, 12, "foo", "(bar)", vec![])])
+            root!(0, 13, vec![root_at_rule!(0, 13, "foo", "(bar)", vec![])])
         );
     }
 
@@ -308,13 +955,13 @@ mod tests {
             "@foo { a: b }",
             root!(
                 0,
-                12,
+                13,
                 vec![root_at_rule!(
                     0,
-                    12,
+                    13,
                     "foo",
                     "",
-                    vec![decl!(7, 10, "a", "b")]
+                    vec![decl!(7, 11, "a", "b")]
                 )]
             )
         );
@@ -326,13 +973,13 @@ mod tests {
             "@foo (bar) { a: b }",
             root!(
                 0,
-                18,
+                19,
                 vec![root_at_rule!(
                     0,
-                    18,
+                    19,
                     "foo",
                     "(bar)",
-                    vec![decl!(13, 16, "a", "b")]
+                    vec![decl!(13, 17, "a", "b")]
                 )]
             )
         );
@@ -344,18 +991,831 @@ mod tests {
             "foo { hello: world ; foo : bar; @foo { a:b } }",
             root!(
                 0,
-                45,
+                46,
                 vec![root_rule!(
                     0,
-                    45,
+                    46,
+                    "foo",
+                    vec![
+                        decl!(6, 18, "hello", "world"),
+                        decl!(21, 30, "foo", "bar"),
+                        at_rule!(32, 44, "foo", "", vec![decl!(39, 42, "a", "b")])
+                    ]
+                )]
+            )
+        );
+    }
+
+    #[test]
+    fn parse_multi_token_value() {
+        assert_parse_ok!(
+            "foo { border: 1px solid red }",
+            root!(
+                0,
+                29,
+                vec![root_rule!(
+                    0,
+                    29,
+                    "foo",
+                    vec![decl!(6, 27, "border", "1px solid red")]
+                )]
+            )
+        );
+    }
+
+    #[test]
+    fn parse_value_with_commas_parens_and_comments() {
+        assert_parse_ok!(
+            "foo{a: rgba(0, 0, 0) /* x */ b /* y */ ;c:d}",
+            root!(
+                0,
+                44,
+                vec![root_rule!(
+                    0,
+                    44,
                     "foo",
                     vec![
-                        decl!(6, 17, "hello", "world"),
-                        decl!(21, 29, "foo", "bar"),
-                        at_rule!(32, 43, "foo", "", vec![decl!(39, 41, "a", "b")])
+                        decl!(4, 30, "a", "rgba(0, 0, 0) /* x */ b"),
+                        decl!(40, 43, "c", "d")
                     ]
                 )]
             )
         );
     }
+
+    #[test]
+    fn parse_missing_value() {
+        assert_parse_err!("foo { a: ; }", ParseErrorKind::MissingValue, 1, 10);
+    }
+
+    #[test]
+    fn parse_strings_in_value() {
+        assert_parse_ok!(
+            r#"a { content: "a;b{" 'c}' }"#,
+            root!(
+                0,
+                26,
+                vec![root_rule!(
+                    0,
+                    26,
+                    "a",
+                    vec![decl!(4, 24, "content", r#""a;b{" 'c}'"#)]
+                )]
+            )
+        );
+    }
+
+    #[test]
+    fn parse_strings_in_selector_and_params() {
+        assert_parse_ok!(
+            r#"[href="/*{"] {} @import "x{" {}"#,
+            root!(
+                0,
+                31,
+                vec![
+                    root_rule!(0, 15, r#"[href="/*{"]"#, vec![]),
+                    root_at_rule!(16, 31, "import", r#""x{""#, vec![])
+                ]
+            )
+        );
+    }
+
+    #[test]
+    fn parse_bad_string() {
+        assert_parse_err!("a { content: \"b\n }", ParseErrorKind::BadString, 1, 14);
+    }
+
+    #[test]
+    fn parse_bodiless_at_rules() {
+        assert_parse_ok!(
+            r#"@charset "utf-8";@import url(x.css) screen; @layer a, b;"#,
+            root!(
+                0,
+                56,
+                vec![
+                    root_at_rule!(0, 17, "charset", r#""utf-8""#),
+                    root_at_rule!(17, 43, "import", "url(x.css) screen"),
+                    root_at_rule!(44, 56, "layer", "a, b")
+                ]
+            )
+        );
+    }
+
+    #[test]
+    fn parse_bodiless_at_rule_at_end_of_input() {
+        assert_parse_ok!(
+            "@layer a",
+            root!(0, 8, vec![root_at_rule!(0, 8, "layer", "a")])
+        );
+    }
+
+    #[test]
+    fn parse_nested_bodiless_at_rule() {
+        assert_parse_ok!(
+            "a { @apply b; c: d; @apply e }",
+            root!(
+                0,
+                30,
+                vec![root_rule!(
+                    0,
+                    30,
+                    "a",
+                    vec![
+                        at_rule!(4, 13, "apply", "b"),
+                        decl!(14, 18, "c", "d"),
+                        at_rule!(20, 28, "apply", "e")
+                    ]
+                )]
+            )
+        );
+    }
+
+    #[test]
+    fn parse_root_rule_starting_with_colon() {
+        assert_parse_ok!(
+            ":root { a: b }",
+            root!(
+                0,
+                14,
+                vec![root_rule!(0, 14, ":root", vec![decl!(8, 12, "a", "b")])]
+            )
+        );
+    }
+
+    #[test]
+    fn parse_nested_rules() {
+        assert_parse_ok!(
+            ".a { color: red; .b { c: d } &:hover { e: f } :is(p) {} }",
+            root!(
+                0,
+                57,
+                vec![root_rule!(
+                    0,
+                    57,
+                    ".a",
+                    vec![
+                        decl!(5, 15, "color", "red"),
+                        rule!(17, 28, ".b", vec![decl!(22, 26, "c", "d")]),
+                        rule!(29, 45, "&:hover", vec![decl!(39, 43, "e", "f")]),
+                        rule!(46, 55, ":is(p)", vec![])
+                    ]
+                )]
+            )
+        );
+    }
+
+    #[test]
+    fn parse_declaration_after_nested_rule() {
+        assert_parse_ok!(
+            "a { b { } c: d }",
+            root!(
+                0,
+                16,
+                vec![root_rule!(
+                    0,
+                    16,
+                    "a",
+                    vec![rule!(4, 9, "b", vec![]), decl!(10, 14, "c", "d")]
+                )]
+            )
+        );
+    }
+
+    #[test]
+    fn parse_error_missing_colon() {
+        assert_eq!(
+            Parser::new("a {\n  color red;\n}").parse(),
+            Err(ParseError {
+                kind: ParseErrorKind::MissingColon,
+                start: 12,
+                end: 15,
+                line: 2,
+                column: 9,
+                expected: "`:`".to_string(),
+                found: "`red`".to_string(),
+            })
+        );
+    }
+
+    #[test]
+    fn parse_error_unclosed_block() {
+        assert_parse_err!("a {}\r\nb { c: d", ParseErrorKind::UnclosedBlock, 2, 3);
+    }
+
+    #[test]
+    fn parse_error_unterminated_comment() {
+        assert_parse_err!(
+            "a { b: c } /* d",
+            ParseErrorKind::UnterminatedComment,
+            1,
+            12
+        );
+        assert_parse_err!(
+            "a { b: c; /* d }",
+            ParseErrorKind::UnterminatedComment,
+            1,
+            11
+        );
+    }
+
+    #[test]
+    fn parse_error_unexpected_token() {
+        assert_parse_err!("a { b: c d; e }", ParseErrorKind::MissingColon, 1, 15);
+        assert_parse_err!("@ {}", ParseErrorKind::UnexpectedToken, 1, 2);
+    }
+
+    #[test]
+    fn parse_non_ascii() {
+        assert_parse_ok!(
+            "/* 😀 */a { content: \"→\" }",
+            root!(
+                0,
+                30,
+                vec![
+                    root_comment!(0, 10, " 😀 "),
+                    root_rule!(10, 30, "a", vec![decl!(14, 28, "content", "\"→\"")])
+                ]
+            )
+        );
+    }
+
+    #[test]
+    fn parse_raws() {
+        let root = Parser::new(
+            "\n@media /* m */ screen /* s */ {\n  a /* b */ , c{ d /* e */ : f /* g */ ;\n  }\n}\n",
+        )
+        .parse()
+        .unwrap();
+
+        assert_eq!(root.raws, Some(RootRaws { after: "\n".into() }));
+
+        let RootChild::AtRule(media) = &root.nodes[0] else {
+            panic!("expected an at-rule");
+        };
+        assert_eq!(
+            media.raws,
+            Some(AtRuleRaws {
+                before: "\n".into(),
+                after_name: " /* m */ ".into(),
+                params: Some(Raw {
+                    value: "screen".into(),
+                    raw: "screen /* s */".into()
+                }),
+                between: " ".into(),
+                after: "\n".into(),
+                semicolon: false,
+            })
+        );
+
+        let Some(BlockChild::Rule(rule)) = media.nodes.as_ref().map(|nodes| &nodes[0]) else {
+            panic!("expected a rule");
+        };
+        assert_eq!(
+            rule.raws,
+            Some(RuleRaws {
+                before: "\n  ".into(),
+                selector: Some(Raw {
+                    value: "a  , c".into(),
+                    raw: "a /* b */ , c".into()
+                }),
+                between: "".into(),
+                after: "\n  ".into(),
+            })
+        );
+
+        let BlockChild::Declaration(decl) = &rule.nodes[0] else {
+            panic!("expected a declaration");
+        };
+        assert_eq!(
+            decl.raws,
+            Some(DeclarationRaws {
+                before: " ".into(),
+                between: " /* e */ : ".into(),
+                value: Some(Raw {
+                    value: "f".into(),
+                    raw: "f /* g */".into()
+                }),
+                important: None,
+                after: " ".into(),
+                semicolon: true,
+            })
+        );
+    }
+
+    #[test]
+    fn parse_bodiless_at_rule_raws() {
+        let root = Parser::new("@import x ;@layer y").parse().unwrap();
+
+        let raws = root
+            .nodes
+            .iter()
+            .map(|child| match child {
+                RootChild::AtRule(at_rule) => at_rule.raws.as_ref().unwrap(),
+                _ => panic!("expected an at-rule"),
+            })
+            .map(|raws| (&*raws.before, &*raws.between, raws.semicolon))
+            .collect::<Vec<_>>();
+
+        assert_eq!(raws, vec![("", " ", true), ("", "", false)]);
+    }
+
+    #[test]
+    fn parse_unexpected_root_token() {
+        assert_parse_err!("a {} }", ParseErrorKind::UnexpectedToken, 1, 6);
+    }
+
+    #[test]
+    fn parse_important() {
+        assert_parse_ok!(
+            "a{b:c!IMPORTANT;d: e f ! /* x */ important /* y */ ;g:h!important}",
+            root!(
+                0,
+                66,
+                vec![root_rule!(
+                    0,
+                    66,
+                    "a",
+                    vec![
+                        decl!(2, 15, "b", "c", true),
+                        decl!(16, 42, "d", "e f", true),
+                        decl!(52, 65, "g", "h", true)
+                    ]
+                )]
+            )
+        );
+    }
+
+    #[test]
+    fn parse_important_raws() {
+        let root = Parser::new("a{d: e /* v */ ! /* x */ important /* y */ ;}")
+            .parse()
+            .unwrap();
+        let RootChild::Rule(rule) = &root.nodes[0] else {
+            panic!("expected a rule");
+        };
+        let BlockChild::Declaration(decl) = &rule.nodes[0] else {
+            panic!("expected a declaration");
+        };
+
+        assert_eq!(decl.value, "e");
+        let raws = decl.raws.as_ref().unwrap();
+        assert_eq!(Raw::get(&raws.value, &decl.value), "e /* v */");
+        assert_eq!(
+            raws.important.as_deref(),
+            Some(" ! /* x */ important /* y */")
+        );
+    }
+
+    #[test]
+    fn parse_borrows_source() {
+        let root = Parser::new("a /* s */ {b: c d}").parse().unwrap();
+        let RootChild::Rule(rule) = &root.nodes[0] else {
+            panic!("expected a rule");
+        };
+        let BlockChild::Declaration(decl) = &rule.nodes[0] else {
+            panic!("expected a declaration");
+        };
+
+        assert!(matches!(rule.selector, Cow::Borrowed("a")));
+        assert!(matches!(decl.prop, Cow::Borrowed("b")));
+        assert!(matches!(decl.value, Cow::Borrowed("c d")));
+    }
+
+    #[test]
+    fn parse_not_important() {
+        assert_parse_ok!(
+            r#"a{content:"!important";b:important;c:d!imp}"#,
+            root!(
+                0,
+                43,
+                vec![root_rule!(
+                    0,
+                    43,
+                    "a",
+                    vec![
+                        decl!(2, 22, "content", r#""!important""#),
+                        decl!(23, 34, "b", "important"),
+                        decl!(35, 42, "c", "d!imp")
+                    ]
+                )]
+            )
+        );
+        assert_parse_err!("a { b: !important }", ParseErrorKind::MissingValue, 1, 19);
+    }
+
+    #[test]
+    fn parse_safe_drops_bad_declarations() {
+        assert_parse_safe!(
+            "a { b c; d: ; e: \"f\n g; h: i }",
+            root!(
+                0,
+                30,
+                vec![root_rule!(0, 30, "a", vec![decl!(24, 28, "h", "i")])]
+            ),
+            vec![
+                ParseErrorKind::MissingColon,
+                ParseErrorKind::MissingValue,
+                ParseErrorKind::BadString
+            ]
+        );
+        assert_parse_safe!(
+            "a { ; \"b\": c; d: e }",
+            root!(
+                0,
+                20,
+                vec![root_rule!(0, 20, "a", vec![decl!(14, 18, "d", "e")])]
+            ),
+            vec![ParseErrorKind::UnexpectedToken]
+        );
+    }
+
+    #[test]
+    fn parse_safe_drops_bad_rules() {
+        assert_parse_safe!(
+            "} a { b: c } d \"e\n{ f: g } h {}",
+            root!(0, 31, vec![root_rule!(27, 31, "h", vec![])]),
+            vec![ParseErrorKind::UnexpectedToken, ParseErrorKind::BadString]
+        );
+        assert_parse_safe!(
+            "a { @ { b: c } d: e } @ f;",
+            root!(
+                0,
+                26,
+                vec![root_rule!(0, 21, "a", vec![decl!(15, 19, "d", "e")])]
+            ),
+            vec![
+                ParseErrorKind::UnexpectedToken,
+                ParseErrorKind::UnexpectedToken
+            ]
+        );
+    }
+
+    #[test]
+    fn parse_safe_closes_blocks_at_end_of_input() {
+        assert_parse_safe!(
+            "a { b { c: d",
+            root!(
+                0,
+                12,
+                vec![root_rule!(
+                    0,
+                    12,
+                    "a",
+                    vec![rule!(4, 12, "b", vec![decl!(8, 12, "c", "d")])]
+                )]
+            ),
+            vec![ParseErrorKind::UnclosedBlock, ParseErrorKind::UnclosedBlock]
+        );
+        assert_parse_safe!(
+            "a { b: c } /* d",
+            root!(
+                0,
+                15,
+                vec![root_rule!(0, 10, "a", vec![decl!(4, 8, "b", "c")])]
+            ),
+            vec![ParseErrorKind::UnterminatedComment]
+        );
+    }
+
+    #[test]
+    fn parse_safe_without_errors() {
+        let input = "a { b: c } @d e;";
+        let (root, errors) = Parser::new(input).parse_safe();
+
+        assert_eq!(Ok(root), Parser::new(input).parse());
+        assert!(errors.is_empty());
+    }
+
+    #[test]
+    fn parse_selectors_of_any_tokens() {
+        assert_parse_ok!(
+            "<!-- .a > #b[c=\"d\"], * { &:hover { e: 1px } } -->",
+            root!(
+                0,
+                49,
+                vec![root_rule!(
+                    5,
+                    45,
+                    ".a > #b[c=\"d\"], *",
+                    vec![rule!(25, 43, "&:hover", vec![decl!(35, 41, "e", "1px")])]
+                )]
+            )
+        );
+    }
+
+    #[test]
+    fn parse_brackets() {
+        assert_parse_ok!(
+            "[a=\"{\"] { b: f(;{)}) [;] } @c (d;}) { e: g }",
+            root!(
+                0,
+                44,
+                vec![
+                    root_rule!(0, 26, "[a=\"{\"]", vec![decl!(10, 24, "b", "f(;{)}) [;]")]),
+                    root_at_rule!(27, 44, "c", "(d;})", vec![decl!(38, 42, "e", "g")])
+                ]
+            )
+        );
+        assert_parse_err!("a { b: (c; }", ParseErrorKind::UnclosedBlock, 1, 3);
+    }
+
+    #[test]
+    fn parse_safe_skips_brackets() {
+        assert_parse_safe!(
+            "a { b; (c;}); d: e }",
+            root!(
+                0,
+                20,
+                vec![root_rule!(0, 20, "a", vec![decl!(14, 18, "d", "e")])]
+            ),
+            vec![
+                ParseErrorKind::MissingColon,
+                ParseErrorKind::UnexpectedToken
+            ]
+        );
+    }
+
+    #[test]
+    fn parse_urls() {
+        assert_parse_ok!(
+            "a { b: url(data:image/png;base64,AA==) }",
+            root!(
+                0,
+                40,
+                vec![root_rule!(
+                    0,
+                    40,
+                    "a",
+                    vec![decl!(4, 38, "b", "url(data:image/png;base64,AA==)")]
+                )]
+            )
+        );
+        assert_parse_err!("a { b: url(c d); }", ParseErrorKind::BadUrl, 1, 8);
+    }
+
+    #[test]
+    fn parse_escapes() {
+        assert_parse_ok!(
+            r"#\31 23 { .sm\:p-4 { p\:a\;d: 1\;2 !\69mportant } }",
+            root!(
+                0,
+                51,
+                vec![root_rule!(
+                    0,
+                    51,
+                    r"#\31 23",
+                    vec![rule!(
+                        10,
+                        49,
+                        r".sm\:p-4",
+                        vec![decl!(21, 47, r"p\:a\;d", r"1\;2", true)]
+                    )]
+                )]
+            )
+        );
+    }
+
+    #[test]
+    fn parse_custom_properties() {
+        assert_parse_ok!(
+            "a { --b: { c: d }; --e: ; --f:  1  /* g */ (;) ; --h:{}}",
+            root!(
+                0,
+                56,
+                vec![root_rule!(
+                    0,
+                    56,
+                    "a",
+                    vec![
+                        decl!(4, 17, "--b", "{ c: d }"),
+                        decl!(19, 24, "--e", ""),
+                        decl!(26, 46, "--f", "1  /* g */ (;)"),
+                        decl!(49, 55, "--h", "{}")
+                    ]
+                )]
+            )
+        );
+    }
+
+    #[test]
+    fn parse_declaration_list() {
+        let mut nodes = Parser::new(" color: red; margin: 0 auto")
+            .parse_declaration_list()
+            .unwrap();
+        clear_raws(&mut nodes);
+
+        assert_eq!(
+            nodes,
+            vec![
+                decl!(1, 11, "color", "red"),
+                decl!(13, 27, "margin", "0 auto")
+            ]
+        );
+        assert_eq!(
+            Parser::new("a: b } c: d").parse_declaration_list(),
+            Err(ParseError {
+                kind: ParseErrorKind::UnexpectedToken,
+                start: 5,
+                end: 6,
+                line: 1,
+                column: 6,
+                expected: "declaration".to_string(),
+                found: "`}`".to_string(),
+            })
+        );
+    }
+
+    #[test]
+    fn parse_declaration_list_safe() {
+        let (mut nodes, errors) =
+            Parser::new("a: b } c; d: e; &:hover { f: g }").parse_declaration_list_safe();
+        clear_raws(&mut nodes);
+
+        assert_eq!(
+            nodes,
+            vec![
+                decl!(0, 4, "a", "b"),
+                decl!(10, 14, "d", "e"),
+                rule!(16, 32, "&:hover", vec![decl!(26, 30, "f", "g")])
+            ]
+        );
+        assert_eq!(
+            errors.iter().map(|err| err.kind).collect::<Vec<_>>(),
+            vec![
+                ParseErrorKind::UnexpectedToken,
+                ParseErrorKind::MissingColon
+            ]
+        );
+    }
+
+    #[test]
+    fn parse_fragments() {
+        let mut rule = Parser::new(" a { b: c } ").parse_rule().unwrap();
+        rule.clear_raws();
+        assert_eq!(
+            BlockChild::Rule(rule),
+            rule!(1, 11, "a", vec![decl!(5, 9, "b", "c")])
+        );
+
+        let mut at_rule = Parser::new("@a b { c {} }").parse_at_rule().unwrap();
+        at_rule.clear_raws();
+        assert_eq!(
+            BlockChild::AtRule(at_rule),
+            at_rule!(0, 13, "a", "b", vec![rule!(7, 11, "c", vec![])])
+        );
+
+        let mut decl = Parser::new("a: b !important;\n")
+            .parse_declaration()
+            .unwrap();
+        decl.raws = None;
+        assert_eq!(BlockChild::Declaration(decl), decl!(0, 15, "a", "b", true));
+
+        let rule = Parser::new("/* a */ /* b */\n.c {}").parse_rule().unwrap();
+        assert_eq!((rule.start, &*rule.raws.unwrap().before), (16, "\n"));
+        let at_rule = Parser::new("/* a */ @b;").parse_at_rule().unwrap();
+        assert_eq!((at_rule.start, &*at_rule.name), (8, "b"));
+        let decl = Parser::new("/* a */c: d").parse_declaration().unwrap();
+        assert_eq!((decl.start, &*decl.prop), (7, "c"));
+
+        assert_eq!(
+            Parser::new(" /* a */ 0 (;) auto /* b */ ").parse_value(),
+            Ok("0 (;) auto".into())
+        );
+    }
+
+    #[test]
+    fn parse_fragments_consume_all_input() {
+        let err = |result: Result<(usize, usize), ParseError>| {
+            result.map_err(|err| (err.kind, err.column)).unwrap_err()
+        };
+        let span = |node: &dyn Node| (node.start(), node.end());
+
+        assert_eq!(
+            err(Parser::new("a {} b {}").parse_rule().map(|n| span(&n))),
+            (ParseErrorKind::UnexpectedToken, 6)
+        );
+        assert_eq!(
+            err(Parser::new("@a {} }").parse_at_rule().map(|n| span(&n))),
+            (ParseErrorKind::UnexpectedToken, 7)
+        );
+        assert_eq!(
+            err(Parser::new("a: b; c: d")
+                .parse_declaration()
+                .map(|n| span(&n))),
+            (ParseErrorKind::UnexpectedToken, 7)
+        );
+        assert_eq!(
+            err(Parser::new("a; b").parse_value().map(|v| (0, v.len()))),
+            (ParseErrorKind::UnexpectedToken, 2)
+        );
+        assert_eq!(
+            err(Parser::new("@a {}").parse_rule().map(|n| span(&n))),
+            (ParseErrorKind::UnexpectedToken, 1)
+        );
+        assert_eq!(
+            err(Parser::new("a {} /* b").parse_rule().map(|n| span(&n))),
+            (ParseErrorKind::UnterminatedComment, 6)
+        );
+        assert_eq!(
+            err(Parser::new("/* a").parse_rule().map(|n| span(&n))),
+            (ParseErrorKind::UnterminatedComment, 1)
+        );
+    }
+
+    #[test]
+    fn parse_max_depth() {
+        assert_eq!(
+            Parser::new("@a { b { @c {} } }")
+                .with_max_depth(2)
+                .parse()
+                .map_err(|err| (err.kind, err.column, err.expected)),
+            Err((
+                ParseErrorKind::NestingTooDeep,
+                13,
+                "at most 2 nested blocks".to_string()
+            ))
+        );
+        let (mut root, errors) = Parser::new("a{b{c{d{}}e:f}}")
+            .with_max_depth(3)
+            .parse_safe();
+        root.clear_raws();
+        assert_eq!(
+            root,
+            root!(
+                0,
+                15,
+                vec![root_rule!(
+                    0,
+                    15,
+                    "a",
+                    vec![rule!(
+                        2,
+                        14,
+                        "b",
+                        vec![rule!(4, 10, "c", vec![]), decl!(10, 13, "e", "f")]
+                    )]
+                )]
+            )
+        );
+        assert_eq!(errors[0].kind, ParseErrorKind::NestingTooDeep);
+    }
+
+    #[test]
+    fn parse_hostile_nesting() {
+        let input = "a{".repeat(100_000);
+        assert_parse_err!(&input, ParseErrorKind::NestingTooDeep, 1, 258);
+    }
+
+    #[test]
+    fn parse_long_declaration_list() {
+        let input = format!("a{{{}}}", "b:c;".repeat(100_000));
+        let root = Parser::new(&input).parse().unwrap();
+
+        let RootChild::Rule(rule) = &root.nodes[0] else {
+            panic!("expected a rule");
+        };
+        assert_eq!(rule.nodes.len(), 100_000);
+    }
+
+    #[test]
+    fn parse_safe_many_errors() {
+        let input = format!("a{{{}}}", "b c;".repeat(100_000));
+        let (root, errors) = Parser::new(&input).parse_safe();
+
+        assert_eq!(errors.len(), 100_000);
+        assert_eq!(errors[99_999].column, 400_001);
+        let RootChild::Rule(rule) = &root.nodes[0] else {
+            panic!("expected a rule");
+        };
+        assert!(rule.nodes.is_empty());
+    }
+
+    #[test]
+    fn parse_empty_declarations() {
+        assert_parse_ok!("a{;}", root!(0, 4, vec![root_rule!(0, 4, "a", vec![])]));
+        assert_parse_ok!(
+            "a{b:c;;d:e}",
+            root!(
+                0,
+                11,
+                vec![root_rule!(
+                    0,
+                    11,
+                    "a",
+                    vec![decl!(2, 5, "b", "c"), decl!(7, 10, "d", "e")]
+                )]
+            )
+        );
+
+        let (root, errors) = Parser::new("a{ ;b:c; ;}").parse_safe();
+        assert_eq!(errors, vec![]);
+        let RootChild::Rule(rule) = &root.nodes[0] else {
+            panic!("expected a rule");
+        };
+        assert_eq!(rule.raws.as_ref().unwrap().after, " ;");
+        let BlockChild::Declaration(decl) = &rule.nodes[0] else {
+            panic!("expected a declaration");
+        };
+        assert_eq!(decl.raws.as_ref().unwrap().before, " ;");
+    }
 }
@@ -0,0 +1,915 @@
+use std::fmt;
+
+/// Source maps nest only a couple of levels, so anything deeper is rejected
+/// rather than risking the stack.
+const MAX_JSON_DEPTH: usize = 32;
+
+const BASE64: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum SourceMapError {
+    InvalidJson,
+    InvalidBase64,
+    InvalidMappings,
+    /// A required field is missing or has the wrong type.
+    InvalidField(&'static str),
+    UnsupportedVersion,
+    /// Not a `data:application/json` URL.
+    UnsupportedUrl,
+}
+
+impl fmt::Display for SourceMapError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use SourceMapError::*;
+        match self {
+            InvalidJson => f.write_str("invalid JSON"),
+            InvalidBase64 => f.write_str("invalid base64"),
+            InvalidMappings => f.write_str("invalid mappings"),
+            InvalidField(field) => write!(f, "invalid `{}` field", field),
+            UnsupportedVersion => f.write_str("unsupported source map version"),
+            UnsupportedUrl => f.write_str("unsupported source map URL"),
+        }
+    }
+}
+
+impl std::error::Error for SourceMapError {}
+
+/// A position in the original source a generated position comes from. Lines
+/// and columns are 0-based, columns count UTF-16 code units.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Original {
+    pub source: usize,
+    pub line: usize,
+    pub column: usize,
+    pub name: Option<usize>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Mapping {
+    pub generated_line: usize,
+    pub generated_column: usize,
+    pub original: Option<Original>,
+}
+
+/// A source map v3, holding its mappings decoded.
+#[derive(Debug, Default, PartialEq)]
+pub struct SourceMap {
+    pub file: Option<String>,
+    pub sources: Vec<String>,
+    pub sources_content: Vec<Option<String>>,
+    pub names: Vec<String>,
+    /// Sorted by generated position.
+    pub mappings: Vec<Mapping>,
+}
+
+impl SourceMap {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Adds a source and returns its index.
+    pub fn add_source(&mut self, name: &str, content: Option<&str>) -> usize {
+        self.sources.push(name.to_string());
+        self.sources_content.push(content.map(str::to_string));
+        self.sources.len() - 1
+    }
+
+    pub fn add_mapping(&mut self, mapping: Mapping) {
+        self.mappings.push(mapping);
+    }
+
+    /// The `mappings` field: base64 VLQ segments, `,` between segments and
+    /// `;` between generated lines.
+    pub fn encode_mappings(&self) -> String {
+        let mut out = String::new();
+        let mut line = 0;
+        let mut first = true;
+        // every field but the generated line is relative to the previous
+        // segment, the generated column only within the same line
+        let mut prev_column = 0;
+        let mut prev_source = 0;
+        let mut prev_line = 0;
+        let mut prev_original_column = 0;
+        let mut prev_name = 0;
+
+        for mapping in &self.mappings {
+            while line < mapping.generated_line {
+                out.push(';');
+                line += 1;
+                prev_column = 0;
+                first = true;
+            }
+            if !first {
+                out.push(',');
+            }
+            first = false;
+
+            encode_vlq(&mut out, mapping.generated_column, prev_column);
+            prev_column = mapping.generated_column;
+
+            if let Some(original) = mapping.original {
+                encode_vlq(&mut out, original.source, prev_source);
+                encode_vlq(&mut out, original.line, prev_line);
+                encode_vlq(&mut out, original.column, prev_original_column);
+                prev_source = original.source;
+                prev_line = original.line;
+                prev_original_column = original.column;
+
+                if let Some(name) = original.name {
+                    encode_vlq(&mut out, name, prev_name);
+                    prev_name = name;
+                }
+            }
+        }
+
+        out
+    }
+
+    pub fn to_json(&self) -> String {
+        let mut out = "{\"version\":3".to_string();
+
+        if let Some(file) = &self.file {
+            out.push_str(",\"file\":");
+            push_json_string(&mut out, file);
+        }
+
+        out.push_str(",\"sources\":[");
+        for (i, source) in self.sources.iter().enumerate() {
+            if i > 0 {
+                out.push(',');
+            }
+            push_json_string(&mut out, source);
+        }
+
+        out.push_str("],\"sourcesContent\":[");
+        for (i, content) in self.sources_content.iter().enumerate() {
+            if i > 0 {
+                out.push(',');
+            }
+            match content {
+                Some(content) => push_json_string(&mut out, content),
+                None => out.push_str("null"),
+            }
+        }
+
+        out.push_str("],\"names\":[");
+        for (i, name) in self.names.iter().enumerate() {
+            if i > 0 {
+                out.push(',');
+            }
+            push_json_string(&mut out, name);
+        }
+
+        out.push_str("],\"mappings\":");
+        push_json_string(&mut out, &self.encode_mappings());
+        out.push('}');
+
+        out
+    }
+
+    /// A `data:` URL embedding the whole map.
+    pub fn to_data_url(&self) -> String {
+        format!(
+            "data:application/json;base64,{}",
+            encode_base64(self.to_json().as_bytes())
+        )
+    }
+
+    /// The comment to append to the CSS to embed the map inline.
+    pub fn to_inline_comment(&self) -> String {
+        annotation(&self.to_data_url())
+    }
+
+    pub fn from_json(json: &str) -> Result<SourceMap, SourceMapError> {
+        use SourceMapError::*;
+
+        let mut parser = JsonParser {
+            json,
+            pos: 0,
+            depth: 0,
+        };
+        let value = parser.parse_value()?;
+        parser.skip_whitespace();
+        if parser.pos != json.len() {
+            return Err(InvalidJson);
+        }
+
+        let Json::Object(fields) = value else {
+            return Err(InvalidJson);
+        };
+        let field = |name: &str| {
+            fields
+                .iter()
+                .find(|(key, _)| key == name)
+                .map(|(_, value)| value)
+        };
+        let strings = |name: &'static str| match field(name) {
+            Some(Json::Array(values)) => values
+                .iter()
+                .map(|value| match value {
+                    Json::String(s) => Ok(Some(s.clone())),
+                    Json::Null => Ok(None),
+                    _ => Err(InvalidField(name)),
+                })
+                .collect::<Result<Vec<_>, _>>(),
+            None => Ok(vec![]),
+            _ => Err(InvalidField(name)),
+        };
+
+        match field("version") {
+            Some(Json::Number(version)) if *version == 3.0 => {}
+            _ => return Err(UnsupportedVersion),
+        }
+
+        let file = match field("file") {
+            Some(Json::String(file)) => Some(file.clone()),
+            _ => None,
+        };
+        let source_root = match field("sourceRoot") {
+            Some(Json::String(root)) if !root.is_empty() && !root.ends_with('/') => {
+                format!("{}/", root)
+            }
+            Some(Json::String(root)) => root.clone(),
+            _ => "".to_string(),
+        };
+        let sources = strings("sources")?
+            .into_iter()
+            .map(|source| format!("{}{}", source_root, source.unwrap_or_default()))
+            .collect::<Vec<_>>();
+        let mut sources_content = strings("sourcesContent")?;
+        sources_content.resize(sources.len(), None);
+        let names: Vec<_> = strings("names")?
+            .into_iter()
+            .map(Option::unwrap_or_default)
+            .collect();
+        let mappings = match field("mappings") {
+            Some(Json::String(mappings)) => decode_mappings(mappings)?,
+            _ => return Err(InvalidField("mappings")),
+        };
+        let in_range = |original: &Original| {
+            original.source < sources.len() && original.name.is_none_or(|name| name < names.len())
+        };
+        if !mappings
+            .iter()
+            .filter_map(|m| m.original.as_ref())
+            .all(in_range)
+        {
+            return Err(InvalidMappings);
+        }
+
+        Ok(SourceMap {
+            file,
+            sources,
+            sources_content,
+            names,
+            mappings,
+        })
+    }
+
+    /// Decodes a map embedded in a `data:application/json` URL, as found in
+    /// inline `sourceMappingURL` annotations.
+    pub fn from_data_url(url: &str) -> Result<SourceMap, SourceMapError> {
+        let (header, data) = url
+            .strip_prefix("data:application/json")
+            .and_then(|rest| rest.split_once(','))
+            .ok_or(SourceMapError::UnsupportedUrl)?;
+
+        if header.split(';').any(|param| param == "base64") {
+            let bytes = decode_base64(data)?;
+            let json = String::from_utf8(bytes).map_err(|_| SourceMapError::InvalidJson)?;
+            SourceMap::from_json(&json)
+        } else {
+            SourceMap::from_json(&percent_decode(data)?)
+        }
+    }
+
+    /// The mapping covering a generated position: the closest one at or
+    /// before it on the same line.
+    pub fn lookup(&self, line: usize, column: usize) -> Option<&Mapping> {
+        let i = self
+            .mappings
+            .partition_point(|m| (m.generated_line, m.generated_column) <= (line, column));
+
+        self.mappings[..i]
+            .last()
+            .filter(|m| m.generated_line == line)
+    }
+
+    /// Chains this map, whose only source is the output `previous` was
+    /// generated for, through `previous`, so that it points to the sources
+    /// of `previous` instead. Mappings `previous` doesn't cover are dropped.
+    pub fn compose(&self, previous: &SourceMap) -> SourceMap {
+        let mappings = self
+            .mappings
+            .iter()
+            .filter_map(|mapping| {
+                let original = mapping.original?;
+                let found = previous.lookup(original.line, original.column)?.original?;
+
+                Some(Mapping {
+                    original: Some(found),
+                    ..*mapping
+                })
+            })
+            .collect();
+
+        SourceMap {
+            file: self.file.clone(),
+            sources: previous.sources.clone(),
+            sources_content: previous.sources_content.clone(),
+            names: previous.names.clone(),
+            mappings,
+        }
+    }
+}
+
+/// The comment to append to the CSS pointing at an external map.
+pub fn annotation(url: &str) -> String {
+    format!("/*# sourceMappingURL={} */", url)
+}
+
+fn encode_vlq(out: &mut String, value: usize, prev: usize) {
+    let delta = value as i64 - prev as i64;
+    // the sign goes in the least significant bit
+    let mut vlq = if delta < 0 {
+        ((-delta) << 1) | 1
+    } else {
+        delta << 1
+    };
+
+    loop {
+        let mut digit = vlq & 0b11111;
+        vlq >>= 5;
+        if vlq > 0 {
+            digit |= 0b100000;
+        }
+        out.push(BASE64[digit as usize] as char);
+        if vlq == 0 {
+            break;
+        }
+    }
+}
+
+pub fn encode_base64(bytes: &[u8]) -> String {
+    let mut out = String::with_capacity(bytes.len().div_ceil(3) * 4);
+
+    for chunk in bytes.chunks(3) {
+        let b = [
+            chunk[0],
+            chunk.get(1).copied().unwrap_or(0),
+            chunk.get(2).copied().unwrap_or(0),
+        ];
+        let n = (b[0] as usize) << 16 | (b[1] as usize) << 8 | b[2] as usize;
+
+        for i in 0..4 {
+            if i <= chunk.len() {
+                out.push(BASE64[(n >> (18 - 6 * i)) & 0b111111] as char);
+            } else {
+                out.push('=');
+            }
+        }
+    }
+
+    out
+}
+
+fn decode_mappings(mappings: &str) -> Result<Vec<Mapping>, SourceMapError> {
+    let mut out = vec![];
+    let mut source = 0;
+    let mut original_line = 0;
+    let mut original_column = 0;
+    let mut name = 0;
+
+    for (generated_line, line) in mappings.split(';').enumerate() {
+        let mut generated_column = 0;
+
+        for segment in line.split(',').filter(|segment| !segment.is_empty()) {
+            let mut bytes = segment.bytes();
+            let mut next = |prev: usize| -> Result<Option<usize>, SourceMapError> {
+                match decode_vlq(&mut bytes)? {
+                    Some(delta) => (prev as i64)
+                        .checked_add(delta)
+                        .and_then(|value| usize::try_from(value).ok())
+                        .map(Some)
+                        .ok_or(SourceMapError::InvalidMappings),
+                    None => Ok(None),
+                }
+            };
+
+            generated_column = next(generated_column)?.ok_or(SourceMapError::InvalidMappings)?;
+
+            let original = match next(source)? {
+                Some(s) => {
+                    source = s;
+                    original_line = next(original_line)?.ok_or(SourceMapError::InvalidMappings)?;
+                    original_column =
+                        next(original_column)?.ok_or(SourceMapError::InvalidMappings)?;
+                    let segment_name = next(name)?;
+                    if let Some(n) = segment_name {
+                        name = n;
+                    }
+
+                    Some(Original {
+                        source,
+                        line: original_line,
+                        column: original_column,
+                        name: segment_name,
+                    })
+                }
+                None => None,
+            };
+
+            out.push(Mapping {
+                generated_line,
+                generated_column,
+                original,
+            });
+        }
+    }
+
+    out.sort_by_key(|m| (m.generated_line, m.generated_column));
+    Ok(out)
+}
+
+/// Decodes a VLQ, which like in other decoders can't take more than 32 bits
+/// sign included.
+fn decode_vlq(bytes: &mut impl Iterator<Item = u8>) -> Result<Option<i64>, SourceMapError> {
+    let mut value = 0i64;
+    let mut shift = 0;
+
+    for b in bytes {
+        let digit = base64_value(b).ok_or(SourceMapError::InvalidMappings)? as i64;
+        value |= (digit & 0b11111) << shift;
+        if value >> 32 != 0 {
+            return Err(SourceMapError::InvalidMappings);
+        }
+        shift += 5;
+
+        if digit & 0b100000 == 0 {
+            let magnitude = value >> 1;
+            return Ok(Some(if value & 1 == 1 {
+                -magnitude
+            } else {
+                magnitude
+            }));
+        }
+    }
+
+    if shift == 0 {
+        Ok(None)
+    } else {
+        Err(SourceMapError::InvalidMappings)
+    }
+}
+
+/// Decodes the `%XX` escapes of a URL as `decodeURIComponent` does, failing
+/// on bad escapes or if the result isn't UTF-8.
+fn percent_decode(data: &str) -> Result<String, SourceMapError> {
+    let bytes = data.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+
+    while i < bytes.len() {
+        if bytes[i] == b'%' {
+            let byte = data
+                .get(i + 1..i + 3)
+                .filter(|hex| hex.bytes().all(|b| b.is_ascii_hexdigit()))
+                .and_then(|hex| u8::from_str_radix(hex, 16).ok())
+                .ok_or(SourceMapError::InvalidJson)?;
+            out.push(byte);
+            i += 3;
+        } else {
+            out.push(bytes[i]);
+            i += 1;
+        }
+    }
+
+    String::from_utf8(out).map_err(|_| SourceMapError::InvalidJson)
+}
+
+fn base64_value(b: u8) -> Option<u8> {
+    BASE64.iter().position(|&c| c == b).map(|i| i as u8)
+}
+
+pub fn decode_base64(data: &str) -> Result<Vec<u8>, SourceMapError> {
+    let data = data.trim_end_matches('=');
+    let mut out = Vec::with_capacity(data.len() * 3 / 4);
+    let mut buffer = 0u32;
+    let mut bits = 0;
+
+    for b in data.bytes() {
+        buffer = buffer << 6 | base64_value(b).ok_or(SourceMapError::InvalidBase64)? as u32;
+        bits += 6;
+        if bits >= 8 {
+            bits -= 8;
+            out.push((buffer >> bits) as u8);
+        }
+    }
+
+    Ok(out)
+}
+
+fn read_hex4(chars: &mut std::str::CharIndices) -> Result<u16, SourceMapError> {
+    let hex = chars.take(4).map(|(_, c)| c).collect::<String>();
+
+    if hex.len() != 4 {
+        return Err(SourceMapError::InvalidJson);
+    }
+    u16::from_str_radix(&hex, 16).map_err(|_| SourceMapError::InvalidJson)
+}
+
+enum Json {
+    Null,
+    Bool,
+    Number(f64),
+    String(String),
+    Array(Vec<Json>),
+    Object(Vec<(String, Json)>),
+}
+
+/// Just enough JSON to read source maps.
+struct JsonParser<'a> {
+    json: &'a str,
+    pos: usize,
+    /// Arrays and objects being parsed.
+    depth: usize,
+}
+
+impl JsonParser<'_> {
+    fn parse_value(&mut self) -> Result<Json, SourceMapError> {
+        self.skip_whitespace();
+
+        match self.peek() {
+            Some(b'{') => {
+                self.enter()?;
+                let mut fields = vec![];
+                if !self.eat(b'}') {
+                    loop {
+                        self.skip_whitespace();
+                        let key = self.parse_string()?;
+                        self.skip_whitespace();
+                        self.expect(b':')?;
+                        fields.push((key, self.parse_value()?));
+                        self.skip_whitespace();
+                        if self.eat(b'}') {
+                            break;
+                        }
+                        self.expect(b',')?;
+                    }
+                }
+                self.depth -= 1;
+                Ok(Json::Object(fields))
+            }
+            Some(b'[') => {
+                self.enter()?;
+                let mut values = vec![];
+                self.skip_whitespace();
+                if !self.eat(b']') {
+                    loop {
+                        values.push(self.parse_value()?);
+                        self.skip_whitespace();
+                        if self.eat(b']') {
+                            break;
+                        }
+                        self.expect(b',')?;
+                    }
+                }
+                self.depth -= 1;
+                Ok(Json::Array(values))
+            }
+            Some(b'"') => Ok(Json::String(self.parse_string()?)),
+            Some(b't') => self.parse_keyword("true", Json::Bool),
+            Some(b'f') => self.parse_keyword("false", Json::Bool),
+            Some(b'n') => self.parse_keyword("null", Json::Null),
+            Some(b'-' | b'0'..=b'9') => {
+                let start = self.pos;
+                while let Some(b'-' | b'+' | b'.' | b'e' | b'E' | b'0'..=b'9') = self.peek() {
+                    self.pos += 1;
+                }
+                self.json[start..self.pos]
+                    .parse()
+                    .map(Json::Number)
+                    .map_err(|_| SourceMapError::InvalidJson)
+            }
+            _ => Err(SourceMapError::InvalidJson),
+        }
+    }
+
+    /// Steps into an array or object.
+    fn enter(&mut self) -> Result<(), SourceMapError> {
+        if self.depth == MAX_JSON_DEPTH {
+            return Err(SourceMapError::InvalidJson);
+        }
+        self.depth += 1;
+        self.pos += 1;
+        Ok(())
+    }
+
+    fn parse_keyword(&mut self, keyword: &str, value: Json) -> Result<Json, SourceMapError> {
+        if self.json[self.pos..].starts_with(keyword) {
+            self.pos += keyword.len();
+            Ok(value)
+        } else {
+            Err(SourceMapError::InvalidJson)
+        }
+    }
+
+    fn parse_string(&mut self) -> Result<String, SourceMapError> {
+        self.expect(b'"')?;
+        let mut out = String::new();
+        let mut chars = self.json[self.pos..].char_indices();
+
+        loop {
+            let (i, c) = chars.next().ok_or(SourceMapError::InvalidJson)?;
+            match c {
+                '"' => {
+                    self.pos += i + 1;
+                    return Ok(out);
+                }
+                '\\' => {
+                    let (_, escape) = chars.next().ok_or(SourceMapError::InvalidJson)?;
+                    match escape {
+                        '"' | '\\' | '/' => out.push(escape),
+                        'b' => out.push('\u{8}'),
+                        'f' => out.push('\u{c}'),
+                        'n' => out.push('\n'),
+                        'r' => out.push('\r'),
+                        't' => out.push('\t'),
+                        'u' => {
+                            let mut units = vec![read_hex4(&mut chars)?];
+                            // a high surrogate is followed by `\u` and the low one
+                            if (0xd800..0xdc00).contains(&units[0])
+                                && chars.as_str().starts_with("\\u")
+                            {
+                                chars.next();
+                                chars.next();
+                                units.push(read_hex4(&mut chars)?);
+                            }
+                            out.extend(
+                                char::decode_utf16(units)
+                                    .map(|c| c.unwrap_or(char::REPLACEMENT_CHARACTER)),
+                            );
+                        }
+                        _ => return Err(SourceMapError::InvalidJson),
+                    }
+                }
+                c => out.push(c),
+            }
+        }
+    }
+
+    fn skip_whitespace(&mut self) {
+        while let Some(b' ' | b'\t' | b'\n' | b'\r') = self.peek() {
+            self.pos += 1;
+        }
+    }
+
+    fn peek(&self) -> Option<u8> {
+        self.json.as_bytes().get(self.pos).copied()
+    }
+
+    fn eat(&mut self, b: u8) -> bool {
+        let matches = self.peek() == Some(b);
+        if matches {
+            self.pos += 1;
+        }
+        matches
+    }
+
+    fn expect(&mut self, b: u8) -> Result<(), SourceMapError> {
+        if self.eat(b) {
+            Ok(())
+        } else {
+            Err(SourceMapError::InvalidJson)
+        }
+    }
+}
+
+fn push_json_string(out: &mut String, s: &str) {
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn mapping(generated: (usize, usize), original: (usize, usize)) -> Mapping {
+        Mapping {
+            generated_line: generated.0,
+            generated_column: generated.1,
+            original: Some(Original {
+                source: 0,
+                line: original.0,
+                column: original.1,
+                name: None,
+            }),
+        }
+    }
+
+    #[test]
+    fn encode_mappings() {
+        let mut map = SourceMap::new();
+        map.add_source("a.css", None);
+        map.add_mapping(mapping((0, 0), (0, 0)));
+        map.add_mapping(mapping((0, 4), (1, 2)));
+        map.add_mapping(mapping((2, 16), (0, 1)));
+        map.add_mapping(Mapping {
+            generated_line: 2,
+            generated_column: 20,
+            original: None,
+        });
+
+        assert_eq!(map.encode_mappings(), "AAAA,IACE;;gBADD,I");
+    }
+
+    #[test]
+    fn to_json() {
+        let mut map = SourceMap::new();
+        map.file = Some("out.css".to_string());
+        map.add_source("in.css", Some("a {\n  b: \"c\"\n}"));
+        map.add_mapping(mapping((0, 0), (0, 0)));
+
+        assert_eq!(
+            map.to_json(),
+            r#"{"version":3,"file":"out.css","sources":["in.css"],"sourcesContent":["a {\n  b: \"c\"\n}"],"names":[],"mappings":"AAAA"}"#
+        );
+    }
+
+    #[test]
+    fn base64() {
+        assert_eq!(encode_base64(b""), "");
+        assert_eq!(encode_base64(b"f"), "Zg==");
+        assert_eq!(encode_base64(b"fo"), "Zm8=");
+        assert_eq!(encode_base64(b"foo"), "Zm9v");
+        assert_eq!(encode_base64(b"foob"), "Zm9vYg==");
+    }
+
+    #[test]
+    fn from_json() {
+        let map = SourceMap::from_json(
+            r#"{
+                "version": 3,
+                "file": "out.css",
+                "sourceRoot": "src",
+                "sources": ["a.scss", "b.scss"],
+                "sourcesContent": ["a\n\"\u00e9\ud83d\ude00"],
+                "names": ["x"],
+                "mappings": "AAAA,IACE;;gBADD,I,CCAAA"
+            }"#,
+        )
+        .unwrap();
+
+        assert_eq!(map.file, Some("out.css".to_string()));
+        assert_eq!(map.sources, vec!["src/a.scss", "src/b.scss"]);
+        assert_eq!(
+            map.sources_content,
+            vec![Some("a\n\"é😀".to_string()), None]
+        );
+        assert_eq!(map.names, vec!["x"]);
+        assert_eq!(
+            map.mappings,
+            vec![
+                mapping((0, 0), (0, 0)),
+                mapping((0, 4), (1, 2)),
+                mapping((2, 16), (0, 1)),
+                Mapping {
+                    generated_line: 2,
+                    generated_column: 20,
+                    original: None
+                },
+                Mapping {
+                    generated_line: 2,
+                    generated_column: 21,
+                    original: Some(Original {
+                        source: 1,
+                        line: 0,
+                        column: 1,
+                        name: Some(0)
+                    })
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn from_data_url() {
+        let mut map = SourceMap::new();
+        map.add_source("a.scss", Some("a { b: c }"));
+        map.add_mapping(mapping((0, 0), (3, 4)));
+
+        assert_eq!(SourceMap::from_data_url(&map.to_data_url()), Ok(map));
+        assert_eq!(
+            SourceMap::from_data_url("a.css.map"),
+            Err(SourceMapError::UnsupportedUrl)
+        );
+        assert_eq!(
+            SourceMap::from_data_url("data:application/json;base64,e30"),
+            Err(SourceMapError::UnsupportedVersion)
+        );
+        assert_eq!(
+            SourceMap::from_data_url(
+                "data:application/json;charset=utf-8,%7B%22version%22%3A3%2C%22sources%22%3A%5B%22%C3%A9.scss%22%5D%2C%22mappings%22%3A%22AAAA%22%7D"
+            )
+            .map(|map| map.sources),
+            Ok(vec!["é.scss".to_string()])
+        );
+        assert_eq!(
+            SourceMap::from_data_url("data:application/json,%7B%2"),
+            Err(SourceMapError::InvalidJson)
+        );
+        assert_eq!(
+            SourceMap::from_data_url("data:application/json,%+7"),
+            Err(SourceMapError::InvalidJson)
+        );
+        assert_eq!(
+            SourceMap::from_json(r#"{"version":3,"mappings":"!"}"#),
+            Err(SourceMapError::InvalidMappings)
+        );
+    }
+
+    #[test]
+    fn from_json_deep_nesting() {
+        let json = format!(r#"{{"version":3,"x":{}"#, "[".repeat(200_000));
+        assert_eq!(
+            SourceMap::from_json(&json),
+            Err(SourceMapError::InvalidJson)
+        );
+
+        let json = format!(
+            r#"{{"version":3,"x":{}{},"mappings":""}}"#,
+            "[".repeat(31),
+            "]".repeat(31)
+        );
+        assert!(SourceMap::from_json(&json).is_ok());
+    }
+
+    #[test]
+    fn from_json_out_of_range() {
+        assert_eq!(
+            SourceMap::from_json(r#"{"version":3,"sources":[],"mappings":"AAAA"}"#),
+            Err(SourceMapError::InvalidMappings)
+        );
+        assert_eq!(
+            SourceMap::from_json(r#"{"version":3,"sources":["a"],"mappings":"AAAAA"}"#),
+            Err(SourceMapError::InvalidMappings)
+        );
+        assert!(SourceMap::from_json(
+            r#"{"version":3,"sources":["a"],"names":["x"],"mappings":"AAAAA"}"#
+        )
+        .is_ok());
+    }
+
+    #[test]
+    fn from_json_overflowing_mappings() {
+        let json = format!(
+            r#"{{"version":3,"sources":["a"],"mappings":"{}"}}"#,
+            ["AAggggggggggggEA"; 5].join(",")
+        );
+        assert_eq!(
+            SourceMap::from_json(&json),
+            Err(SourceMapError::InvalidMappings)
+        );
+        assert_eq!(
+            SourceMap::from_json(r#"{"version":3,"sources":["a"],"mappings":"AAggggggEA"}"#),
+            Err(SourceMapError::InvalidMappings)
+        );
+        assert!(
+            SourceMap::from_json(r#"{"version":3,"sources":["a"],"mappings":"AA+/////DA"}"#)
+                .is_ok()
+        );
+    }
+
+    #[test]
+    fn compose() {
+        let mut previous = SourceMap::new();
+        previous.add_source("a.scss", None);
+        previous.add_mapping(mapping((0, 0), (1, 0)));
+        previous.add_mapping(mapping((0, 4), (2, 2)));
+
+        let mut map = SourceMap::new();
+        map.add_source("a.css", None);
+        map.add_mapping(mapping((0, 0), (0, 0)));
+        map.add_mapping(mapping((0, 2), (0, 6)));
+        map.add_mapping(mapping((1, 0), (1, 0)));
+
+        let composed = map.compose(&previous);
+
+        assert_eq!(composed.sources, vec!["a.scss"]);
+        assert_eq!(
+            composed.mappings,
+            vec![mapping((0, 0), (1, 0)), mapping((0, 2), (2, 2))]
+        );
+    }
+}
@@ -0,0 +1,583 @@
+use ast::*;
+use sourcemap::{Mapping, Original, SourceMap, SourceMapError};
+use std::{fmt, io};
+use visitor::*;
+
+const INDENT: &str = "    ";
+
+/// Prints a tree back to CSS. Parsed nodes are printed with their raws, so
+/// an untouched tree reproduces its source exactly, while nodes built by
+/// hand are printed in a default style.
+pub struct Stringifier<'a, W: fmt::Write> {
+    out: W,
+    /// The source lines counted as in `write`, and the map being built.
+    map: Option<(LineIndex<'a>, SourceMap)>,
+    /// The map of the source itself, e.g. from Sass, to chain through.
+    previous_map: Option<SourceMap>,
+    /// 0-based line and UTF-16 column reached in the output, tracked only
+    /// when building a source map.
+    line: usize,
+    column: usize,
+    result: fmt::Result,
+    depth: usize,
+    first: bool,
+    /// Set after a statement printed without its `;`, which is only allowed
+    /// before the end of a block.
+    semicolon: bool,
+}
+
+impl<'a, W: fmt::Write> Stringifier<'a, W> {
+    pub fn new(out: W) -> Self {
+        Stringifier {
+            out,
+            map: None,
+            previous_map: None,
+            line: 0,
+            column: 0,
+            result: Ok(()),
+            depth: 0,
+            first: true,
+            semicolon: false,
+        }
+    }
+
+    /// Also builds a source map pointing back to `source`, the text the tree
+    /// was parsed from, listed as `source_name`. Nodes with an empty span,
+    /// like the ones built by hand, are not mapped, and neither are nodes
+    /// parsed from another string whose span doesn't fit `source`.
+    pub fn with_source_map(out: W, source_name: &str, source: &'a str) -> Self {
+        let mut map = SourceMap::new();
+        map.add_source(source_name, Some(source));
+
+        Stringifier {
+            map: Some((LineIndex::for_source_map(source), map)),
+            ..Stringifier::new(out)
+        }
+    }
+
+    /// Makes the source map point through `map`, the map of the source, to
+    /// the files the source was generated from.
+    pub fn set_previous_map(&mut self, map: SourceMap) {
+        self.previous_map = Some(map);
+    }
+
+    /// Takes the source map built so far.
+    pub fn take_source_map(&mut self) -> Option<SourceMap> {
+        let (_, map) = self.map.take()?;
+
+        Some(match &self.previous_map {
+            Some(previous) => map.compose(previous),
+            None => map,
+        })
+    }
+
+    pub fn stringify(&mut self, root: &Root) -> fmt::Result {
+        // the hooks never stop the walk
+        let _ = walk_root(self, root);
+        std::mem::replace(&mut self.result, Ok(()))
+    }
+
+    pub fn into_inner(self) -> W {
+        self.out
+    }
+
+    fn write(&mut self, s: &str) {
+        if self.result.is_ok() {
+            self.result = self.out.write_str(s);
+        }
+
+        if self.map.is_some() {
+            let mut chars = s.chars().peekable();
+            while let Some(c) = chars.next() {
+                match c {
+                    '\r' if chars.peek() == Some(&'\n') => {}
+                    '\n' | '\r' => {
+                        self.line += 1;
+                        self.column = 0;
+                    }
+                    c => self.column += c.len_utf16(),
+                }
+            }
+        }
+    }
+
+    /// Maps the current output position to `offset` in the source.
+    fn mark(&mut self, offset: usize) {
+        if let Some((lines, map)) = &mut self.map {
+            let Some(position) = lines.position(offset) else {
+                return;
+            };
+            // source maps count lines from 0 and columns in UTF-16
+            let line_start = lines.line_start(position.line);
+            let column = lines.source()[line_start..offset].encode_utf16().count();
+
+            map.add_mapping(Mapping {
+                generated_line: self.line,
+                generated_column: self.column,
+                original: Some(Original {
+                    source: 0,
+                    line: position.line - 1,
+                    column,
+                    name: None,
+                }),
+            });
+        }
+    }
+
+    /// The text of `node` in the source, when the node has a span that fits
+    /// it. Nodes parsed from another string can have spans outside the
+    /// source or off a char boundary.
+    fn source_text<N: Node>(&self, node: &N) -> Option<&'a str> {
+        let (lines, _) = self.map.as_ref()?;
+        lines
+            .source()
+            .get(node.start()..node.end())
+            .filter(|text| !text.is_empty())
+    }
+
+    /// Writes what comes before a node and maps the start of the node, which
+    /// in the source starts with `expected`.
+    fn write_start<N: Node>(&mut self, node: &N, before: Option<&str>, expected: &str) {
+        self.write_before(before);
+        if self
+            .source_text(node)
+            .is_some_and(|text| text.starts_with(expected))
+        {
+            self.mark(node.start());
+        }
+    }
+
+    fn write_before(&mut self, before: Option<&str>) {
+        if self.semicolon {
+            self.write(";");
+            self.semicolon = false;
+        }
+
+        match before {
+            Some(before) => self.write(before),
+            None if self.depth > 0 => {
+                self.write("\n");
+                self.write(&INDENT.repeat(self.depth));
+            }
+            None if !self.first => self.write("\n"),
+            None => {}
+        }
+        self.first = false;
+    }
+
+    fn open_block(&mut self) {
+        self.write("{");
+        self.depth += 1;
+    }
+
+    fn close_block<N: Node>(&mut self, node: &N, after: Option<&str>, is_empty: bool) {
+        self.depth -= 1;
+        self.semicolon = false;
+
+        match after {
+            Some(after) => self.write(after),
+            None if !is_empty => {
+                self.write("\n");
+                self.write(&INDENT.repeat(self.depth));
+            }
+            None => {}
+        }
+        if self
+            .source_text(node)
+            .is_some_and(|text| text.ends_with('}'))
+        {
+            self.mark(node.end() - 1);
+        }
+        self.write("}");
+    }
+}
+
+impl<W: fmt::Write> Visitor for Stringifier<'_, W> {
+    fn leave_root(&mut self, root: &Root) -> VisitAction {
+        if let Some(raws) = &root.raws {
+            self.write(&raws.after);
+        }
+        VisitAction::Continue
+    }
+
+    fn visit_rule(&mut self, rule: &Rule) -> VisitAction {
+        let raws = rule.raws.as_ref();
+
+        self.write_start(rule, raws.map(|raws| &*raws.before), "");
+        match raws {
+            Some(raws) => {
+                self.write(Raw::get(&raws.selector, &rule.selector));
+                self.write(&raws.between);
+            }
+            None => {
+                self.write(&rule.selector);
+                self.write(" ");
+            }
+        }
+        self.open_block();
+        VisitAction::Continue
+    }
+
+    fn leave_rule(&mut self, rule: &Rule) -> VisitAction {
+        let after = rule.raws.as_ref().map(|raws| &*raws.after);
+        self.close_block(rule, after, rule.nodes.is_empty());
+        VisitAction::Continue
+    }
+
+    fn visit_at_rule(&mut self, at_rule: &AtRule) -> VisitAction {
+        let raws = at_rule.raws.as_ref();
+
+        self.write_start(at_rule, raws.map(|raws| &*raws.before), "@");
+        self.write("@");
+        self.write(&at_rule.name);
+        match raws {
+            Some(raws) => {
+                self.write(&raws.after_name);
+                self.write(Raw::get(&raws.params, &at_rule.params));
+                self.write(&raws.between);
+            }
+            None => {
+                if !at_rule.params.is_empty() {
+                    self.write(" ");
+                    self.write(&at_rule.params);
+                }
+                if at_rule.nodes.is_some() {
+                    self.write(" ");
+                }
+            }
+        }
+
+        match &at_rule.nodes {
+            Some(_) => self.open_block(),
+            None if raws.is_none_or(|raws| raws.semicolon) => self.write(";"),
+            None => self.semicolon = true,
+        }
+        VisitAction::Continue
+    }
+
+    fn leave_at_rule(&mut self, at_rule: &AtRule) -> VisitAction {
+        if let Some(nodes) = &at_rule.nodes {
+            let after = at_rule.raws.as_ref().map(|raws| &*raws.after);
+            self.close_block(at_rule, after, nodes.is_empty());
+        }
+        VisitAction::Continue
+    }
+
+    fn visit_declaration(&mut self, decl: &Declaration) -> VisitAction {
+        let raws = decl.raws.as_ref();
+
+        self.write_start(decl, raws.map(|raws| &*raws.before), "");
+        self.write(&decl.prop);
+        match raws {
+            Some(raws) => {
+                self.write(&raws.between);
+                self.write(Raw::get(&raws.value, &decl.value));
+                if decl.important {
+                    self.write(raws.important.as_deref().unwrap_or(" !important"));
+                }
+                self.write(&raws.after);
+                if raws.semicolon {
+                    self.write(";");
+                } else {
+                    self.semicolon = true;
+                }
+            }
+            None => {
+                self.write(": ");
+                self.write(&decl.value);
+                if decl.important {
+                    self.write(" !important");
+                }
+                self.write(";");
+            }
+        }
+        VisitAction::Continue
+    }
+
+    fn visit_comment(&mut self, comment: &Comment) -> VisitAction {
+        // the annotation points to the map of the source, not of the output
+        if self.map.is_some() && comment.source_map_url().is_some() {
+            return VisitAction::Continue;
+        }
+
+        let before = comment.raws.as_ref().map(|raws| &*raws.before);
+        self.write_start(comment, before, "/*");
+        self.write("/*");
+        self.write(&comment.text);
+        self.write("*/");
+        VisitAction::Continue
+    }
+}
+
+pub fn stringify(root: &Root) -> String {
+    let mut stringifier = Stringifier::new(String::new());
+    stringifier
+        .stringify(root)
+        .expect("writing to a String cannot fail");
+    stringifier.into_inner()
+}
+
+/// Prints `root`, parsed from `source`, along with a source map of the
+/// output. The map can be saved next to the CSS with `SourceMap::to_json` or
+/// appended to it with `SourceMap::to_inline_comment`. When the source embeds
+/// its own map inline, the output map is chained through it, and an error is
+/// returned if that map can't be decoded. Maps in separate files are left to
+/// the caller.
+pub fn stringify_with_source_map(
+    root: &Root,
+    source_name: &str,
+    source: &str,
+) -> Result<(String, SourceMap), SourceMapError> {
+    let mut stringifier = Stringifier::with_source_map(String::new(), source_name, source);
+    match root.source_map_url().map(SourceMap::from_data_url) {
+        Some(Ok(previous)) => stringifier.set_previous_map(previous),
+        Some(Err(SourceMapError::UnsupportedUrl)) | None => {}
+        Some(Err(err)) => return Err(err),
+    }
+    stringifier
+        .stringify(root)
+        .expect("writing to a String cannot fail");
+    let map = stringifier.take_source_map().unwrap_or_default();
+    Ok((stringifier.into_inner(), map))
+}
+
+/// Writes the CSS of `root` to `out`, e.g. a `File`.
+pub fn stringify_io<W: io::Write>(root: &Root, out: W) -> io::Result<()> {
+    let mut stringifier = Stringifier::new(IoAdapter { out, error: None });
+    let result = stringifier.stringify(root);
+
+    match stringifier.into_inner().error {
+        Some(err) => Err(err),
+        None => result.map_err(|_| io::Error::other("formatter error")),
+    }
+}
+
+struct IoAdapter<W: io::Write> {
+    out: W,
+    error: Option<io::Error>,
+}
+
+impl<W: io::Write> fmt::Write for IoAdapter<W> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.out.write_all(s.as_bytes()).map_err(|err| {
+            self.error = Some(err);
+            fmt::Error
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use macros::*;
+    use parser::Parser;
+
+    fn round_trip(css: &str) {
+        assert_eq!(stringify(&Parser::new(css).parse().unwrap()), css);
+    }
+
+    #[test]
+    fn stringify_round_trip() {
+        round_trip("");
+        round_trip("a{b:c}");
+        round_trip("\n/* hello */\nfoo { hello: world ; foo : bar; @foo { a:b } }\n\n");
+        round_trip("a /* x */ , b /* y */ {\n  c /* d */ : e /* f */ ;\n  g: h\n}");
+        round_trip("@charset \"utf-8\";\n@import url(x.css) screen ;\n@layer a");
+        round_trip("@media /* m */ screen and (min-width: 1px) {\n  a { b: c; }\n}\n");
+        round_trip(".a {\n  color: red;\n  &:hover { color: blue }\n  @apply b\n}");
+        round_trip("a { content: \"→ ; }\" }");
+        round_trip("a { b: c !IMPORTANT; d: e/* x */! /* y */important }");
+        round_trip("a{;}");
+        round_trip("a { b: c;; d: e ; ; }\n@media x { ; a { ;b:c } ; }");
+    }
+
+    #[test]
+    fn stringify_default_style() {
+        let root = root!(
+            0,
+            0,
+            vec![
+                root_at_rule!(0, 0, "import", "\"x.css\""),
+                root_rule!(
+                    0,
+                    0,
+                    "a",
+                    vec![
+                        decl!(0, 0, "color", "red", true),
+                        at_rule!(0, 0, "media", "print", vec![decl!(0, 0, "b", "c")]),
+                        rule!(0, 0, "&:hover", vec![])
+                    ]
+                ),
+                root_comment!(0, 0, " end ")
+            ]
+        );
+
+        assert_eq!(
+            stringify(&root),
+            "@import \"x.css\";\na {\n    color: red !important;\n    @media print {\n        b: c;\n    }\n    &:hover {}\n}\n/* end */"
+        );
+    }
+
+    #[test]
+    fn stringify_mixed_raws() {
+        let mut root = Parser::new("a{b:c}\n").parse().unwrap();
+        if let RootChild::Rule(rule) = &mut root.nodes[0] {
+            rule.selector = "a, p".into();
+            rule.nodes.push(decl!(0, 0, "d", "e"));
+        }
+
+        assert_eq!(stringify(&root), "a, p{b:c;\n    d: e;}\n");
+    }
+
+    #[test]
+    fn stringify_edited_commented_prelude() {
+        let mut root =
+            Parser::new("a /* first */, b { c: d }\n@media screen /* x */ and (min-width: 1px) {}")
+                .parse()
+                .unwrap();
+        for node in &mut root.nodes {
+            match node {
+                RootChild::Rule(rule) => rule.selector = format!(".x {}", rule.selector).into(),
+                RootChild::AtRule(at_rule) => {
+                    at_rule.params = format!("print, {}", at_rule.params).into()
+                }
+                _ => {}
+            }
+        }
+
+        assert_eq!(
+            stringify(&root),
+            ".x a , b { c: d }\n@media print, screen  and (min-width: 1px) {}"
+        );
+    }
+
+    #[test]
+    fn stringify_to_io() {
+        let root = Parser::new("a { b: c }").parse().unwrap();
+        let mut out = vec![];
+
+        stringify_io(&root, &mut out).unwrap();
+
+        assert_eq!(out, b"a { b: c }");
+    }
+
+    #[test]
+    fn stringify_source_map() {
+        let source = "a {\n  b: c;\n}";
+        let mut root = Parser::new(source).parse().unwrap();
+        if let RootChild::Rule(rule) = &mut root.nodes[0] {
+            rule.raws.as_mut().unwrap().before = "/* → */\n".into();
+            rule.nodes.insert(0, decl!(0, 0, "x", "y"));
+        }
+
+        let (css, map) = stringify_with_source_map(&root, "in.css", source).unwrap();
+
+        assert_eq!(css, "/* → */\na {\n    x: y;\n  b: c;\n}");
+        assert_eq!(
+            map.mappings
+                .iter()
+                .map(|m| {
+                    let original = m.original.unwrap();
+                    (
+                        (m.generated_line, m.generated_column),
+                        (original.line, original.column),
+                    )
+                })
+                .collect::<Vec<_>>(),
+            vec![((1, 0), (0, 0)), ((3, 2), (1, 2)), ((4, 0), (2, 0))]
+        );
+        assert_eq!(
+            map.to_json(),
+            r#"{"version":3,"sources":["in.css"],"sourcesContent":["a {\n  b: c;\n}"],"names":[],"mappings":";AAAA;;EACE;AACF"}"#
+        );
+        assert!(map
+            .to_inline_comment()
+            .starts_with("/*# sourceMappingURL=data:application/json;base64,eyJ2ZXJzaW9uIjoz"));
+    }
+
+    #[test]
+    fn stringify_source_map_foreign_nodes() {
+        let source = "é{}\n";
+        let mut root = Parser::new(source).parse().unwrap();
+        // spans out of the source and off a char boundary
+        root.prepend(Parser::new("@media x {}").parse_at_rule().unwrap());
+        root.prepend(Parser::new(" a{}").parse_rule().unwrap());
+
+        let (css, map) = stringify_with_source_map(&root, "in.css", source).unwrap();
+
+        assert_eq!(css, " a{}@media x {}é{}\n");
+        assert_eq!(
+            map.mappings
+                .iter()
+                .map(|m| {
+                    let original = m.original.unwrap();
+                    (
+                        (m.generated_line, m.generated_column),
+                        (original.line, original.column),
+                    )
+                })
+                .collect::<Vec<_>>(),
+            vec![((0, 15), (0, 0)), ((0, 17), (0, 2))]
+        );
+    }
+
+    #[test]
+    fn stringify_source_map_form_feed() {
+        let source = "a{}\u{c}b{}\r\nc{}";
+        let root = Parser::new(source).parse().unwrap();
+
+        let (_, map) = stringify_with_source_map(&root, "in.css", source).unwrap();
+
+        assert_eq!(
+            map.mappings
+                .iter()
+                .filter_map(|m| m.original)
+                .map(|original| (original.line, original.column))
+                .collect::<Vec<_>>(),
+            vec![(0, 0), (0, 2), (0, 4), (0, 6), (1, 0), (1, 2)]
+        );
+    }
+
+    #[test]
+    fn stringify_chained_source_map() {
+        let mut previous = SourceMap::new();
+        previous.add_source("a.scss", Some("$x: c;\na {\n  b: $x;\n}"));
+        for (generated, original) in [((0, 0), (1, 0)), ((1, 2), (2, 2)), ((2, 0), (3, 0))] {
+            previous.add_mapping(Mapping {
+                generated_line: generated.0,
+                generated_column: generated.1,
+                original: Some(Original {
+                    source: 0,
+                    line: original.0,
+                    column: original.1,
+                    name: None,
+                }),
+            });
+        }
+        let source = format!("a {{\n  b: c;\n}}\n{}", previous.to_inline_comment());
+        let root = Parser::new(&source).parse().unwrap();
+
+        let (css, map) = stringify_with_source_map(&root, "a.css", &source).unwrap();
+
+        assert_eq!(css, "a {\n  b: c;\n}");
+        assert_eq!(map.sources, vec!["a.scss"]);
+        assert_eq!(map.encode_mappings(), previous.encode_mappings());
+    }
+
+    #[test]
+    fn stringify_bad_inline_source_map() {
+        let source = format!(
+            "a{{}}\n/*# sourceMappingURL=data:application/json,{} */",
+            "[".repeat(200_000)
+        );
+        let root = Parser::new(&source).parse().unwrap();
+        assert_eq!(
+            stringify_with_source_map(&root, "a.css", &source),
+            Err(SourceMapError::InvalidJson)
+        );
+
+        let source = "a{}\n/*# sourceMappingURL=a.css.map */";
+        let root = Parser::new(source).parse().unwrap();
+        assert!(stringify_with_source_map(&root, "a.css", source).is_ok());
+    }
+}
@@ -1,5 +1,10 @@
-use core::str::Chars;
-use std::iter::{Enumerate, Peekable};
+//! A tokenizer following the CSS Syntax Level 3 spec. Tokens keep the byte
+//! range they span in the input instead of their value, so that the source
+//! can be sliced for them.
+
+use core::str::CharIndices;
+use std::borrow::Cow;
+use std::iter::Peekable;
 
 const NEWLINE: char = '\n';
 const SPACE: char = ' ';
@@ -10,33 +15,133 @@ const SLASH: char = '/';
 const ASTERISK: char = '*';
 const OPEN_CURLY: char = '{';
 const CLOSED_CURLY: char = '}';
+const OPEN_SQUARE: char = '[';
+const CLOSED_SQUARE: char = ']';
+const OPEN_PAREN: char = '(';
+const CLOSED_PAREN: char = ')';
 const SEMICOLON: char = ';';
 const COLON: char = ':';
+const COMMA: char = ',';
 const AT: char = '@';
+const HASH: char = '#';
+const PLUS: char = '+';
+const MINUS: char = '-';
+const DOT: char = '.';
+const LESS_THAN: char = '<';
+const PERCENT: char = '%';
+const DOUBLE_QUOTE: char = '"';
+const SINGLE_QUOTE: char = '\'';
+const BACKSLASH: char = '\\';
+const REPLACEMENT: char = '\u{fffd}';
 
-#[derive(Debug, PartialEq)]
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub enum TokenKind {
     Space,
     Comment,
-    Word,
-    OpenCurly,
-    ClosedCurly,
+    Ident,
+    /// An identifier followed by `(`, which is part of the token.
+    Function,
+    /// `@` followed by an identifier.
+    AtKeyword,
+    /// `#` followed by name characters. `id` tells whether they also form
+    /// an identifier, as ID selectors require.
+    Hash {
+        id: bool,
+    },
+    String,
+    /// A string ended by a newline, which is not part of the token.
+    BadString,
+    /// `url(` with an unquoted URL, up to the closing `)`. `start..end` is
+    /// the byte range of the URL, without the surrounding whitespace, see
+    /// `Token::value`.
+    Url {
+        start: usize,
+        end: usize,
+    },
+    /// A `url(` with quotes, `(` or bad escapes within an unquoted URL, up
+    /// to the next `)`.
+    BadUrl,
+    Number {
+        value: f64,
+        integer: bool,
+    },
+    Percentage {
+        value: f64,
+    },
+    /// A number followed by a unit starting at the byte offset `unit_start`
+    /// and running to the end of the token, see `Token::unit`.
+    Dimension {
+        value: f64,
+        integer: bool,
+        unit_start: usize,
+    },
+    /// `<!--`
+    Cdo,
+    /// `-->`
+    Cdc,
     Colon,
     Semicolon,
-    At,
+    Comma,
+    OpenSquare,
+    ClosedSquare,
+    OpenParen,
+    ClosedParen,
+    OpenCurly,
+    ClosedCurly,
+    /// Any other character.
+    Delim(char),
 }
 
-#[derive(Debug, PartialEq)]
+/// A token of `TokenKind` spanning the byte range `start..end` of the input.
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub struct Token(pub TokenKind, pub usize, pub usize);
 
+impl Token {
+    /// The text of the token in `source`, the input it was read from.
+    pub fn raw<'a>(&self, source: &'a str) -> &'a str {
+        &source[self.1..self.2]
+    }
+
+    /// The value the token stands for, with escape sequences replaced: the
+    /// name of an identifier, function, at-keyword or hash, the contents of
+    /// a string and the URL of a url token. Other tokens are their raw text.
+    pub fn value<'a>(&self, source: &'a str) -> Cow<'a, str> {
+        use TokenKind::*;
+        let raw = self.raw(source);
+
+        match &self.0 {
+            Ident => unescape(raw),
+            Function => unescape(&raw[..raw.len() - 1]),
+            AtKeyword | Hash { .. } => unescape(&raw[1..]),
+            String | BadString => {
+                let quote = raw.chars().next();
+                unescape_until(&raw[1..], quote.filter(|_| self.0 == String))
+            }
+            Url { start, end } => unescape(&source[*start..*end]),
+            _ => Cow::Borrowed(raw),
+        }
+    }
+
+    /// The unit of a dimension, with escape sequences replaced.
+    pub fn unit<'a>(&self, source: &'a str) -> Option<Cow<'a, str>> {
+        match self.0 {
+            TokenKind::Dimension { unit_start, .. } => Some(unescape(&source[unit_start..self.2])),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone)]
 pub struct Tokenizer<'a> {
-    iter: Peekable<Enumerate<Chars<'a>>>,
+    input: &'a str,
+    iter: Peekable<CharIndices<'a>>,
 }
 
 impl<'a> Tokenizer<'a> {
     pub fn new(input: &'a str) -> Tokenizer<'a> {
         Tokenizer {
-            iter: input.chars().enumerate().peekable(),
+            input,
+            iter: input.char_indices().peekable(),
         }
     }
 
@@ -44,141 +149,416 @@ impl<'a> Tokenizer<'a> {
         let mut iter = self.iter.clone();
         iter.nth(n)
     }
+
+    fn peek(&self, n: usize) -> Option<char> {
+        self.iter.clone().nth(n).map(|(_, c)| c)
+    }
+
+    /// The offset of the next character.
+    fn pos(&mut self) -> usize {
+        self.iter.peek().map_or(self.input.len(), |&(pos, _)| pos)
+    }
+
+    fn bump(&mut self) -> Option<char> {
+        self.iter.next().map(|(_, c)| c)
+    }
+
+    fn bump_while(&mut self, condition: fn(char) -> bool) {
+        while self.peek(0).is_some_and(condition) {
+            self.bump();
+        }
+    }
+
+    /// Consumes a newline, `\r\n` counting as one.
+    fn bump_newline(&mut self) {
+        if self.bump() == Some(CR) && self.peek(0) == Some(NEWLINE) {
+            self.bump();
+        }
+    }
+
+    fn starts_escape(&self, n: usize) -> bool {
+        is_escape(self.peek(n), self.peek(n + 1))
+    }
+
+    fn starts_ident(&self, n: usize) -> bool {
+        match self.peek(n) {
+            Some(MINUS) => {
+                self.peek(n + 1)
+                    .is_some_and(|c| is_name_start(c) || c == MINUS)
+                    || self.starts_escape(n + 1)
+            }
+            Some(BACKSLASH) => self.starts_escape(n),
+            Some(c) => is_name_start(c),
+            None => false,
+        }
+    }
+
+    fn starts_number(&self) -> bool {
+        let digit = |n| self.peek(n).is_some_and(|c: char| c.is_ascii_digit());
+        match self.peek(0) {
+            Some(PLUS | MINUS) => digit(1) || (self.peek(1) == Some(DOT) && digit(2)),
+            Some(DOT) => digit(1),
+            Some(c) => c.is_ascii_digit(),
+            None => false,
+        }
+    }
+
+    /// Consumes an escape sequence, past its `\`, and returns the character
+    /// it stands for.
+    fn consume_escape(&mut self) -> char {
+        match self.peek(0) {
+            Some(c) if c.is_ascii_hexdigit() => {
+                let start = self.pos();
+                for _ in 0..6 {
+                    if !self.peek(0).is_some_and(|c| c.is_ascii_hexdigit()) {
+                        break;
+                    }
+                    self.bump();
+                }
+                let code = u32::from_str_radix(&self.input[start..self.pos()], 16).unwrap_or(0);
+
+                // a single whitespace ends the escape
+                if self.peek(0).is_some_and(is_white_space) {
+                    self.bump_newline();
+                }
+
+                // zero, surrogates and out of range code points are invalid
+                match char::from_u32(code) {
+                    Some('\0') | None => REPLACEMENT,
+                    Some(c) => c,
+                }
+            }
+            Some(_) => self.bump().unwrap_or(REPLACEMENT),
+            None => REPLACEMENT,
+        }
+    }
+
+    fn consume_name(&mut self) {
+        loop {
+            match self.peek(0) {
+                Some(c) if is_name(c) => {
+                    self.bump();
+                }
+                Some(BACKSLASH) if self.starts_escape(0) => {
+                    self.bump();
+                    self.consume_escape();
+                }
+                _ => break,
+            }
+        }
+    }
+
+    /// Consumes a number and returns its value and whether it was written
+    /// as an integer.
+    fn consume_number(&mut self) -> (f64, bool) {
+        let digit = |c: char| c.is_ascii_digit();
+        let is_digit = |this: &Self, n| this.peek(n).is_some_and(digit);
+        let start = self.pos();
+        let mut integer = true;
+
+        if let Some(PLUS | MINUS) = self.peek(0) {
+            self.bump();
+        }
+        self.bump_while(digit);
+
+        if self.peek(0) == Some(DOT) && is_digit(self, 1) {
+            integer = false;
+            self.bump();
+            self.bump_while(digit);
+        }
+
+        if let Some('e' | 'E') = self.peek(0) {
+            let sign = matches!(self.peek(1), Some(PLUS | MINUS)) as usize;
+            if is_digit(self, 1 + sign) {
+                integer = false;
+                for _ in 0..=sign {
+                    self.bump();
+                }
+                self.bump_while(digit);
+            }
+        }
+
+        let value = self.input[start..self.pos()].parse().unwrap_or(0.0);
+        (value, integer)
+    }
+
+    fn consume_numeric(&mut self) -> TokenKind {
+        let (value, integer) = self.consume_number();
+
+        if self.starts_ident(0) {
+            let unit_start = self.pos();
+            self.consume_name();
+            TokenKind::Dimension {
+                value,
+                integer,
+                unit_start,
+            }
+        } else if self.peek(0) == Some(PERCENT) {
+            self.bump();
+            TokenKind::Percentage { value }
+        } else {
+            TokenKind::Number { value, integer }
+        }
+    }
+
+    fn consume_ident_like(&mut self) -> TokenKind {
+        let start = self.pos();
+        self.consume_name();
+
+        if self.peek(0) != Some(OPEN_PAREN) {
+            return TokenKind::Ident;
+        }
+        let is_url = unescape(&self.input[start..self.pos()]).eq_ignore_ascii_case("url");
+        self.bump();
+
+        // a quoted URL is a function taking a string
+        let quoted = self
+            .iter
+            .clone()
+            .map(|(_, c)| c)
+            .find(|&c| !is_white_space(c));
+        if is_url && !matches!(quoted, Some(DOUBLE_QUOTE | SINGLE_QUOTE)) {
+            self.consume_url()
+        } else {
+            TokenKind::Function
+        }
+    }
+
+    fn consume_url(&mut self) -> TokenKind {
+        self.bump_while(is_white_space);
+        let start = self.pos();
+        let end;
+
+        loop {
+            match self.peek(0) {
+                Some(CLOSED_PAREN) => {
+                    end = self.pos();
+                    self.bump();
+                    break;
+                }
+                None => {
+                    end = self.pos();
+                    break;
+                }
+                Some(c) if is_white_space(c) => {
+                    end = self.pos();
+                    self.bump_while(is_white_space);
+                    match self.peek(0) {
+                        Some(CLOSED_PAREN) => {
+                            self.bump();
+                            break;
+                        }
+                        None => break,
+                        _ => return self.consume_bad_url(),
+                    }
+                }
+                Some(DOUBLE_QUOTE | SINGLE_QUOTE | OPEN_PAREN) => return self.consume_bad_url(),
+                Some(c) if is_non_printable(c) => return self.consume_bad_url(),
+                Some(BACKSLASH) if self.starts_escape(0) => {
+                    self.bump();
+                    self.consume_escape();
+                }
+                Some(BACKSLASH) => return self.consume_bad_url(),
+                Some(_) => {
+                    self.bump();
+                }
+            }
+        }
+        TokenKind::Url { start, end }
+    }
+
+    /// Consumes the rest of a bad URL up to `)`, so that parsing can resume
+    /// after it.
+    fn consume_bad_url(&mut self) -> TokenKind {
+        loop {
+            match self.peek(0) {
+                Some(CLOSED_PAREN) => {
+                    self.bump();
+                    break;
+                }
+                None => break,
+                Some(BACKSLASH) if self.starts_escape(0) => {
+                    self.bump();
+                    self.consume_escape();
+                }
+                Some(_) => {
+                    self.bump();
+                }
+            }
+        }
+        TokenKind::BadUrl
+    }
+
+    fn consume_string(&mut self, quote: char) -> TokenKind {
+        loop {
+            match self.peek(0) {
+                Some(c) if c == quote => {
+                    self.bump();
+                    break;
+                }
+                // an unescaped newline ends the string without being part of it
+                Some(NEWLINE | CR | FEED) => return TokenKind::BadString,
+                Some(BACKSLASH) => {
+                    self.bump();
+                    match self.peek(0) {
+                        None => {}
+                        // an escaped newline continues the string
+                        Some(NEWLINE | CR | FEED) => self.bump_newline(),
+                        Some(_) => {
+                            self.consume_escape();
+                        }
+                    }
+                }
+                None => break,
+                Some(_) => {
+                    self.bump();
+                }
+            }
+        }
+        TokenKind::String
+    }
+
+    fn consume_comment(&mut self) -> TokenKind {
+        loop {
+            match self.bump() {
+                Some(ASTERISK) if self.peek(0) == Some(SLASH) => {
+                    self.bump();
+                    break;
+                }
+                None => break,
+                Some(_) => {}
+            }
+        }
+        TokenKind::Comment
+    }
 }
 
-macro_rules! match_white_space {
-    () => {
-        Some((_, SPACE)) | Some((_, TAB)) | Some((_, NEWLINE)) | Some((_, CR)) | Some((_, FEED))
-    };
+/// The value of an identifier, or of any name, with its escape sequences
+/// replaced by the characters they stand for, as in `sm\:p-4` for `sm:p-4`.
+pub fn unescape(name: &str) -> Cow<'_, str> {
+    unescape_until(name, None)
 }
 
-macro_rules! match_word_ending {
-    () => {
-        Some((_, OPEN_CURLY))
-            | Some((_, CLOSED_CURLY))
-            | Some((_, COLON))
-            | Some((_, SEMICOLON))
-            | Some((_, AT))
-    };
+/// Unescapes `text` up to the unescaped `quote` closing a string, dropping
+/// the escaped newlines that continue a string over several lines.
+fn unescape_until(text: &str, quote: Option<char>) -> Cow<'_, str> {
+    if !text.contains(BACKSLASH) {
+        return Cow::Borrowed(match quote {
+            Some(quote) => text.strip_suffix(quote).unwrap_or(text),
+            None => text,
+        });
+    }
+
+    let mut tokenizer = Tokenizer::new(text);
+    let mut value = String::with_capacity(text.len());
+    while let Some(c) = tokenizer.bump() {
+        match c {
+            c if Some(c) == quote => break,
+            BACKSLASH => match tokenizer.peek(0) {
+                Some(NEWLINE | CR | FEED) => tokenizer.bump_newline(),
+                None if quote.is_some() => {}
+                _ => value.push(tokenizer.consume_escape()),
+            },
+            c => value.push(c),
+        }
+    }
+    Cow::Owned(value)
 }
 
-macro_rules! consume {
-    ($input:ident, $start:ident) => {
-        if let Some((pos, _)) = $input.next() {
-            $start = pos
-        };
-    };
-    ($input:ident, $expr:expr) => {
-        if let Some((_, _)) = $input.next() {
-            $expr
-        };
-    };
+fn is_white_space(c: char) -> bool {
+    matches!(c, SPACE | TAB | NEWLINE | CR | FEED)
+}
+
+fn is_name_start(c: char) -> bool {
+    c.is_ascii_alphabetic() || c == '_' || !c.is_ascii()
+}
+
+fn is_name(c: char) -> bool {
+    is_name_start(c) || c.is_ascii_digit() || c == MINUS
 }
 
-macro_rules! T {
-    ($kind:ident, $start:expr, $end:expr) => {
-        Some(Token(TokenKind::$kind, $start, $end))
-    };
+fn is_non_printable(c: char) -> bool {
+    matches!(c, '\0'..='\u{8}' | '\u{b}' | '\u{e}'..='\u{1f}' | '\u{7f}')
 }
 
-macro_rules! match_token {
-    ($expr:ident) => {
-        Some((_, $expr))
-    };
+/// Whether `first` and `second` start an escape sequence, which a newline
+/// cannot follow.
+fn is_escape(first: Option<char>, second: Option<char>) -> bool {
+    first == Some(BACKSLASH) && !matches!(second, Some(NEWLINE | CR | FEED))
 }
 
 impl Iterator for Tokenizer<'_> {
     type Item = Token;
 
     fn next(&mut self) -> Option<Token> {
-        let input = self.iter.by_ref();
-        let mut start = 0;
-        let mut offset = 0;
-        match input.peek() {
-            match_white_space!() => {
-                consume!(input, start);
-                while let match_white_space!() = input.peek() {
-                    consume!(input, offset += 1);
-                }
-                T!(Space, start, start + offset)
-            }
-            match_token!(SLASH) => {
-                consume!(input, start);
-                if let match_token!(ASTERISK) = input.peek() {
-                    consume!(input, offset += 1);
-                    loop {
-                        match input.peek() {
-                            match_token!(ASTERISK) => {
-                                if let match_token!(SLASH) = input.clone().nth(1) {
-                                    consume!(input, offset += 1);
-                                    consume!(input, offset += 1);
-                                    break;
-                                } else {
-                                    consume!(input, offset += 1);
-                                }
-                            }
-                            None => break,
-                            _ => {
-                                consume!(input, offset += 1);
-                            }
-                        }
-                    }
-                    T!(Comment, start, start + offset)
-                } else {
-                    loop {
-                        match input.peek() {
-                            match_word_ending!() | match_white_space!() => break,
-                            None => break,
-                            _ => {
-                                consume!(input, offset += 1);
-                            }
-                        }
-                    }
-                    T!(Word, start, start + offset)
-                }
+        use TokenKind::*;
+        let start = self.pos();
+        let c = self.peek(0)?;
+
+        let kind = match c {
+            c if is_white_space(c) => {
+                self.bump_while(is_white_space);
+                Space
             }
-            match_token!(OPEN_CURLY) => {
-                consume!(input, start);
-                T!(OpenCurly, start, start + offset)
+            SLASH if self.peek(1) == Some(ASTERISK) => {
+                self.bump();
+                self.bump();
+                self.consume_comment()
             }
-            match_token!(CLOSED_CURLY) => {
-                consume!(input, start);
-                T!(ClosedCurly, start, start + offset)
+            DOUBLE_QUOTE | SINGLE_QUOTE => {
+                self.bump();
+                self.consume_string(c)
             }
-            match_token!(COLON) => {
-                consume!(input, start);
-                T!(Colon, start, start + offset)
+            HASH if self.peek(1).is_some_and(is_name) || self.starts_escape(1) => {
+                self.bump();
+                let id = self.starts_ident(0);
+                self.consume_name();
+                Hash { id }
             }
-            match_token!(SEMICOLON) => {
-                consume!(input, start);
-                T!(Semicolon, start, start + offset)
+            PLUS | DOT if self.starts_number() => self.consume_numeric(),
+            MINUS if self.starts_number() => self.consume_numeric(),
+            MINUS if self.peek(1) == Some(MINUS) && self.peek(2) == Some('>') => {
+                self.bump();
+                self.bump();
+                self.bump();
+                Cdc
+            }
+            MINUS | BACKSLASH if self.starts_ident(0) => self.consume_ident_like(),
+            LESS_THAN
+                if self.peek(1) == Some('!')
+                    && self.peek(2) == Some(MINUS)
+                    && self.peek(3) == Some(MINUS) =>
+            {
+                for _ in 0..4 {
+                    self.bump();
+                }
+                Cdo
             }
-            match_token!(AT) => {
-                consume!(input, start);
-                T!(At, start, start + offset)
+            AT if self.starts_ident(1) => {
+                self.bump();
+                self.consume_name();
+                AtKeyword
             }
-            None => None,
+            c if c.is_ascii_digit() => self.consume_numeric(),
+            c if is_name_start(c) => self.consume_ident_like(),
             _ => {
-                consume!(input, start);
-                loop {
-                    match input.peek() {
-                        match_word_ending!() | match_white_space!() => break,
-                        match_token!(SLASH) => {
-                            if let match_token!(ASTERISK) = input.clone().nth(1) {
-                                break;
-                            } else {
-                                consume!(input, offset += 1);
-                            }
-                        }
-                        None => break,
-                        _ => {
-                            consume!(input, offset += 1);
-                        }
-                    }
+                self.bump();
+                match c {
+                    OPEN_CURLY => OpenCurly,
+                    CLOSED_CURLY => ClosedCurly,
+                    OPEN_SQUARE => OpenSquare,
+                    CLOSED_SQUARE => ClosedSquare,
+                    OPEN_PAREN => OpenParen,
+                    CLOSED_PAREN => ClosedParen,
+                    COLON => Colon,
+                    SEMICOLON => Semicolon,
+                    COMMA => Comma,
+                    c => Delim(c),
                 }
-                T!(Word, start, start + offset)
             }
-        }
+        };
+
+        Some(Token(kind, start, self.pos()))
     }
 }
 
@@ -193,23 +573,254 @@ mod tests {
         assert_eq!(
             res,
             vec![
-                Token(Comment, 0, 8),
-                Token(Space, 9, 13),
-                Token(Word, 14, 16),
-                Token(Space, 17, 17),
-                Token(OpenCurly, 18, 18),
-                Token(Space, 19, 19),
-                Token(Word, 20, 22),
-                Token(Colon, 23, 23),
-                Token(Space, 24, 24),
-                Token(Word, 25, 27),
-                Token(Semicolon, 28, 28),
-                Token(Space, 29, 29),
-                Token(ClosedCurly, 30, 30),
-                Token(Word, 31, 33),
-                Token(Space, 34, 34),
-                Token(Word, 35, 37),
-                Token(Comment, 38, 44),
+                Token(Comment, 0, 9),
+                Token(Space, 9, 14),
+                Token(Ident, 14, 17),
+                Token(Space, 17, 18),
+                Token(OpenCurly, 18, 19),
+                Token(Space, 19, 20),
+                Token(Ident, 20, 23),
+                Token(Colon, 23, 24),
+                Token(Space, 24, 25),
+                Token(Ident, 25, 28),
+                Token(Semicolon, 28, 29),
+                Token(Space, 29, 30),
+                Token(ClosedCurly, 30, 31),
+                Token(Ident, 31, 34),
+                Token(Space, 34, 35),
+                Token(Ident, 35, 38),
+                Token(Comment, 38, 45),
+            ]
+        );
+    }
+
+    #[test]
+    fn tokenize_strings() {
+        let t = Tokenizer::new(r#"a:"b;{\"c"'d'"#);
+        let res = t.collect::<Vec<Token>>();
+        assert_eq!(
+            res,
+            vec![
+                Token(Ident, 0, 1),
+                Token(Colon, 1, 2),
+                Token(String, 2, 10),
+                Token(String, 10, 13),
+            ]
+        );
+    }
+
+    #[test]
+    fn tokenize_bad_string() {
+        let t = Tokenizer::new("'a\nb");
+        let res = t.collect::<Vec<Token>>();
+        assert_eq!(
+            res,
+            vec![
+                Token(BadString, 0, 2),
+                Token(Space, 2, 3),
+                Token(Ident, 3, 4),
+            ]
+        );
+    }
+
+    #[test]
+    fn tokenize_non_ascii() {
+        let t = Tokenizer::new("a:\"→\"/* 😀 */é");
+        let res = t.collect::<Vec<Token>>();
+        assert_eq!(
+            res,
+            vec![
+                Token(Ident, 0, 1),
+                Token(Colon, 1, 2),
+                Token(String, 2, 7),
+                Token(Comment, 7, 17),
+                Token(Ident, 17, 19),
+            ]
+        );
+    }
+
+    fn kinds(input: &str) -> Vec<TokenKind> {
+        Tokenizer::new(input).map(|Token(kind, ..)| kind).collect()
+    }
+
+    #[test]
+    fn tokenize_numbers() {
+        let number = |value, integer| Number { value, integer };
+        let dimension = |value, integer, unit_start| Dimension {
+            value,
+            integer,
+            unit_start,
+        };
+        let input = "1 -2.5 +.5e3 1e-2 50% 10px -1\\31 x 1e 1.a";
+
+        assert_eq!(
+            kinds(input),
+            vec![
+                number(1.0, true),
+                Space,
+                number(-2.5, false),
+                Space,
+                number(500.0, false),
+                Space,
+                number(0.01, false),
+                Space,
+                Percentage { value: 50.0 },
+                Space,
+                dimension(10.0, true, 24),
+                Space,
+                dimension(-1.0, true, 29),
+                Space,
+                dimension(1.0, true, 36),
+                Space,
+                number(1.0, true),
+                Delim('.'),
+                Ident,
+            ]
+        );
+        assert_eq!(
+            Tokenizer::new(input)
+                .filter_map(|token| token.unit(input))
+                .collect::<Vec<_>>(),
+            vec!["px", "1x", "e"]
+        );
+    }
+
+    #[test]
+    fn tokenize_idents_and_delims() {
+        assert_eq!(
+            kinds("-a --b -- - -1a \\31 a .a #a #1 # @a @ @-"),
+            vec![
+                Ident,
+                Space,
+                Ident,
+                Space,
+                Ident,
+                Space,
+                Delim('-'),
+                Space,
+                Dimension {
+                    value: -1.0,
+                    integer: true,
+                    unit_start: 14
+                },
+                Space,
+                Ident,
+                Space,
+                Delim('.'),
+                Ident,
+                Space,
+                Hash { id: true },
+                Space,
+                Hash { id: false },
+                Space,
+                Delim('#'),
+                Space,
+                AtKeyword,
+                Space,
+                Delim('@'),
+                Space,
+                Delim('@'),
+                Delim('-'),
+            ]
+        );
+    }
+
+    #[test]
+    fn tokenize_punctuation() {
+        assert_eq!(
+            kinds("<!--a(b)[c],d:e;-->\\\n!"),
+            vec![
+                Cdo,
+                Function,
+                Ident,
+                ClosedParen,
+                OpenSquare,
+                Ident,
+                ClosedSquare,
+                Comma,
+                Ident,
+                Colon,
+                Ident,
+                Semicolon,
+                Cdc,
+                Delim('\\'),
+                Space,
+                Delim('!'),
+            ]
+        );
+    }
+
+    #[test]
+    fn tokenize_urls() {
+        let url = |start, end| Url { start, end };
+        let t = Tokenizer::new("url( a;b ) URL(\"c\") url(d e) url(f\\)) url(g");
+        let res = t.collect::<Vec<Token>>();
+        assert_eq!(
+            res,
+            vec![
+                Token(url(5, 8), 0, 10),
+                Token(Space, 10, 11),
+                Token(Function, 11, 15),
+                Token(String, 15, 18),
+                Token(ClosedParen, 18, 19),
+                Token(Space, 19, 20),
+                Token(BadUrl, 20, 28),
+                Token(Space, 28, 29),
+                Token(url(33, 36), 29, 37),
+                Token(Space, 37, 38),
+                Token(url(42, 43), 38, 43),
+            ]
+        );
+        let input = r"url(data:image/png;base64,AA==) u\72l(\31 x) url( h  )";
+        assert_eq!(
+            Tokenizer::new(input)
+                .filter(|token| token.0 != Space)
+                .map(|token| token.value(input))
+                .collect::<Vec<_>>(),
+            vec!["data:image/png;base64,AA==", "1x", "h"]
+        );
+    }
+
+    #[test]
+    fn tokenize_escapes() {
+        let t = Tokenizer::new("a\\:b \"\\41\n\\\r\nc\" \\D800 x");
+        let res = t.collect::<Vec<Token>>();
+        assert_eq!(
+            res,
+            vec![
+                Token(Ident, 0, 4),
+                Token(Space, 4, 5),
+                Token(String, 5, 15),
+                Token(Space, 15, 16),
+                Token(Ident, 16, 23),
+            ]
+        );
+        assert_eq!(
+            unescape("\\D800 x\\0\\110000\\"),
+            "\u{fffd}x\u{fffd}\u{fffd}\u{fffd}"
+        );
+        assert_eq!(unescape("\\31 23\\:"), "123:");
+    }
+
+    #[test]
+    fn token_values() {
+        let input = r#"sm\:p-4 f\(( @m\65 dia #\31 23 "a\"b\
+c" 'd\'' url(e\)) 'f"#;
+        let res = Tokenizer::new(input)
+            .filter(|token| token.0 != Space)
+            .map(|token| (token.raw(input), token.value(input).into_owned()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            res,
+            vec![
+                (r"sm\:p-4", "sm:p-4".to_string()),
+                (r"f\((", "f(".to_string()),
+                (r"@m\65 dia", "media".to_string()),
+                (r"#\31 23", "123".to_string()),
+                ("\"a\\\"b\\\nc\"", "a\"bc".to_string()),
+                (r"'d\''", "d'".to_string()),
+                (r"url(e\))", "e)".to_string()),
+                ("'f", "f".to_string()),
             ]
         );
     }
@@ -1,117 +1,487 @@
 use ast::*;
+use std::ops::{Deref, DerefMut};
+
+/// What a walk does after calling a hook.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[must_use = "hooks don't walk the tree, call `walk_root` or `walk_root_mut`"]
+pub enum VisitAction {
+    /// Visits the children of the node, if any, then its next siblings.
+    Continue,
+    /// Goes on with the next siblings without visiting the children of the
+    /// node. The leave hook of the node is still called.
+    SkipChildren,
+    /// Ends the walk without calling any other hook.
+    Stop,
+}
 
+/// Hooks called by [`walk_root`]: `visit_*` when entering a node and
+/// `leave_*` once its children have been visited.
+///
+/// The hooks only look at their node. A walk is started with
+/// `walk_root(&mut visitor, &root)`, and `visitor.visit_root(&root)`, which
+/// used to walk the whole tree, now does nothing more than the hook.
 pub trait Visitor {
-    fn visit_root(&mut self, root: &Root)
-    where
-        Self: Sized,
-    {
-        walk_root(self, root);
+    fn visit_root(&mut self, _: &Root) -> VisitAction {
+        VisitAction::Continue
     }
 
-    fn visit_rule(&mut self, rule: &Rule)
-    where
-        Self: Sized,
-    {
-        walk_rule(self, rule);
+    fn leave_root(&mut self, _: &Root) -> VisitAction {
+        VisitAction::Continue
     }
 
-    fn visit_at_rule(&mut self, at_rule: &AtRule)
-    where
-        Self: Sized,
-    {
-        walk_at_rule(self, at_rule);
+    fn visit_rule(&mut self, _: &Rule) -> VisitAction {
+        VisitAction::Continue
     }
 
-    fn visit_declaration(&mut self, _: &Declaration) {}
+    fn leave_rule(&mut self, _: &Rule) -> VisitAction {
+        VisitAction::Continue
+    }
+
+    fn visit_at_rule(&mut self, _: &AtRule) -> VisitAction {
+        VisitAction::Continue
+    }
+
+    fn leave_at_rule(&mut self, _: &AtRule) -> VisitAction {
+        VisitAction::Continue
+    }
+
+    fn visit_declaration(&mut self, _: &Declaration) -> VisitAction {
+        VisitAction::Continue
+    }
 
-    fn visit_comment(&mut self, _: &Comment) {}
+    fn visit_comment(&mut self, _: &Comment) -> VisitAction {
+        VisitAction::Continue
+    }
 }
 
-pub fn walk_root<V: Visitor>(visitor: &mut V, root: &Root) {
-    for child in &root.nodes {
-        match child {
-            RootChild::Rule(rule) => visitor.visit_rule(rule),
-            RootChild::AtRule(at_rule) => visitor.visit_at_rule(at_rule),
-            RootChild::Comment(comment) => visitor.visit_comment(comment),
+/// Walks the tree depth first. Returns `Stop` if a hook stopped the walk.
+pub fn walk_root<V: Visitor>(visitor: &mut V, root: &Root) -> VisitAction {
+    match visitor.visit_root(root) {
+        VisitAction::Continue => {
+            for child in &root.nodes {
+                let action = match child {
+                    RootChild::Rule(rule) => walk_rule(visitor, rule),
+                    RootChild::AtRule(at_rule) => walk_at_rule(visitor, at_rule),
+                    RootChild::Comment(comment) => visitor.visit_comment(comment),
+                };
+                if action == VisitAction::Stop {
+                    return VisitAction::Stop;
+                }
+            }
         }
+        VisitAction::SkipChildren => {}
+        VisitAction::Stop => return VisitAction::Stop,
     }
+    visitor.leave_root(root)
 }
 
-pub fn walk_rule<V: Visitor>(visitor: &mut V, rule: &Rule) {
-    for child in &rule.nodes {
-        match child {
-            BlockChild::Declaration(decl) => visitor.visit_declaration(decl),
-            BlockChild::AtRule(at_rule) => visitor.visit_at_rule(at_rule),
-            BlockChild::Comment(comment) => visitor.visit_comment(comment),
+pub fn walk_rule<V: Visitor>(visitor: &mut V, rule: &Rule) -> VisitAction {
+    match visitor.visit_rule(rule) {
+        VisitAction::Continue => {
+            if walk_block(visitor, &rule.nodes) == VisitAction::Stop {
+                return VisitAction::Stop;
+            }
         }
+        VisitAction::SkipChildren => {}
+        VisitAction::Stop => return VisitAction::Stop,
     }
+    visitor.leave_rule(rule)
 }
 
-pub fn walk_at_rule<V: Visitor>(visitor: &mut V, at_rule: &AtRule) {
-    for child in &at_rule.nodes {
-        match child {
+pub fn walk_at_rule<V: Visitor>(visitor: &mut V, at_rule: &AtRule) -> VisitAction {
+    match visitor.visit_at_rule(at_rule) {
+        VisitAction::Continue => {
+            let nodes = at_rule.nodes.as_deref().unwrap_or_default();
+            if walk_block(visitor, nodes) == VisitAction::Stop {
+                return VisitAction::Stop;
+            }
+        }
+        VisitAction::SkipChildren => {}
+        VisitAction::Stop => return VisitAction::Stop,
+    }
+    visitor.leave_at_rule(at_rule)
+}
+
+fn walk_block<V: Visitor>(visitor: &mut V, nodes: &[BlockChild]) -> VisitAction {
+    for child in nodes {
+        let action = match child {
+            BlockChild::Rule(rule) => walk_rule(visitor, rule),
             BlockChild::Declaration(decl) => visitor.visit_declaration(decl),
-            BlockChild::AtRule(at_rule) => visitor.visit_at_rule(at_rule),
+            BlockChild::AtRule(at_rule) => walk_at_rule(visitor, at_rule),
             BlockChild::Comment(comment) => visitor.visit_comment(comment),
+        };
+        if action == VisitAction::Stop {
+            return VisitAction::Stop;
         }
     }
+    VisitAction::Continue
 }
 
+/// Hooks called by [`walk_root_mut`], like the ones of [`Visitor`]. A walk
+/// is started with `walk_root_mut(&mut visitor, &mut root)`, not with
+/// `visit_root`.
 pub trait VisitorMut {
-    fn visit_root(&mut self, root: &mut Root)
-    where
-        Self: Sized,
-    {
-        walk_root_mut(self, root);
+    fn visit_root(&mut self, _: &mut Root) -> VisitAction {
+        VisitAction::Continue
     }
 
-    fn visit_rule(&mut self, rule: &mut Rule)
-    where
-        Self: Sized,
-    {
-        walk_rule_mut(self, rule);
+    fn leave_root(&mut self, _: &mut Root) -> VisitAction {
+        VisitAction::Continue
+    }
+
+    fn visit_rule<'a, C: Sibling<'a>>(&mut self, _: &mut NodeMut<Rule<'a>, C>) -> VisitAction {
+        VisitAction::Continue
+    }
+
+    fn leave_rule<'a, C: Sibling<'a>>(&mut self, _: &mut NodeMut<Rule<'a>, C>) -> VisitAction {
+        VisitAction::Continue
+    }
+
+    fn visit_at_rule<'a, C: Sibling<'a>>(&mut self, _: &mut NodeMut<AtRule<'a>, C>) -> VisitAction {
+        VisitAction::Continue
+    }
+
+    fn leave_at_rule<'a, C: Sibling<'a>>(&mut self, _: &mut NodeMut<AtRule<'a>, C>) -> VisitAction {
+        VisitAction::Continue
+    }
+
+    fn visit_declaration<'a>(
+        &mut self,
+        _: &mut NodeMut<Declaration<'a>, BlockChild<'a>>,
+    ) -> VisitAction {
+        VisitAction::Continue
+    }
+
+    fn visit_comment<'a, C: Sibling<'a>>(
+        &mut self,
+        _: &mut NodeMut<Comment<'a>, C>,
+    ) -> VisitAction {
+        VisitAction::Continue
     }
+}
+
+/// The children of a root or of a block, among which a [`NodeMut`] sits.
+/// Rules, at-rules and comments fit in both, declarations only in blocks.
+pub trait Sibling<'a>: From<Rule<'a>> + From<AtRule<'a>> + From<Comment<'a>> {
+    fn as_rule_mut(&mut self) -> Option<&mut Rule<'a>>;
+
+    fn as_at_rule_mut(&mut self) -> Option<&mut AtRule<'a>>;
 
-    fn visit_at_rule(&mut self, at_rule: &mut AtRule)
+    fn as_comment_mut(&mut self) -> Option<&mut Comment<'a>>;
+}
+
+macro_rules! impl_sibling {
+    ($($child: ident),*) => {
+        $(impl<'a> Sibling<'a> for $child<'a> {
+            fn as_rule_mut(&mut self) -> Option<&mut Rule<'a>> {
+                match self {
+                    $child::Rule(rule) => Some(rule),
+                    _ => None,
+                }
+            }
+
+            fn as_at_rule_mut(&mut self) -> Option<&mut AtRule<'a>> {
+                match self {
+                    $child::AtRule(at_rule) => Some(at_rule),
+                    _ => None,
+                }
+            }
+
+            fn as_comment_mut(&mut self) -> Option<&mut Comment<'a>> {
+                match self {
+                    $child::Comment(comment) => Some(comment),
+                    _ => None,
+                }
+            }
+        })*
+    };
+}
+
+impl_sibling!(RootChild, BlockChild);
+
+/// A node that can sit among the siblings `C`.
+pub trait Child<C>: Clone + Into<C> {
+    fn from_sibling_mut(sibling: &mut C) -> Option<&mut Self>;
+}
+
+impl<'a, C: Sibling<'a>> Child<C> for Rule<'a> {
+    fn from_sibling_mut(sibling: &mut C) -> Option<&mut Self> {
+        sibling.as_rule_mut()
+    }
+}
+
+impl<'a, C: Sibling<'a>> Child<C> for AtRule<'a> {
+    fn from_sibling_mut(sibling: &mut C) -> Option<&mut Self> {
+        sibling.as_at_rule_mut()
+    }
+}
+
+impl<'a, C: Sibling<'a>> Child<C> for Comment<'a> {
+    fn from_sibling_mut(sibling: &mut C) -> Option<&mut Self> {
+        sibling.as_comment_mut()
+    }
+}
+
+impl<'a> Child<BlockChild<'a>> for Declaration<'a> {
+    fn from_sibling_mut<'b>(sibling: &'b mut BlockChild<'a>) -> Option<&'b mut Self> {
+        match sibling {
+            BlockChild::Declaration(decl) => Some(decl),
+            _ => None,
+        }
+    }
+}
+
+/// The node a [`VisitorMut`] is visiting, among siblings of type `C`, along
+/// with the changes to make around it. The changes are applied once the node
+/// and its children have been visited, and the nodes they insert are not
+/// visited. Only nodes that fit among `C` can be inserted, so a declaration
+/// can't end up in the root:
+///
+/// ```compile_fail,E0277
+/// use ast::*;
+/// use visitor::*;
+///
+/// struct Flatten;
+///
+/// impl VisitorMut for Flatten {
+///     fn visit_rule<'a, C: Sibling<'a>>(&mut self, rule: &mut NodeMut<Rule<'a>, C>) -> VisitAction {
+///         let decl = Declaration {
+///             prop: "a".into(),
+///             value: "b".into(),
+///             important: false,
+///             raws: None,
+///             start: 0,
+///             end: 0,
+///         };
+///         rule.replace_with([decl]);
+///         VisitAction::Continue
+///     }
+/// }
+/// ```
+pub struct NodeMut<'b, T, C> {
+    node: &'b mut T,
+    edit: &'b mut Edit<C>,
+}
+
+struct Edit<C> {
+    before: Vec<C>,
+    after: Vec<C>,
+    remove: bool,
+}
+
+impl<C> Default for Edit<C> {
+    fn default() -> Self {
+        Edit {
+            before: Vec::new(),
+            after: Vec::new(),
+            remove: false,
+        }
+    }
+}
+
+impl<T: Child<C>, C> NodeMut<'_, T, C> {
+    /// Removes the node from its parent.
+    pub fn remove(&mut self) {
+        self.edit.remove = true;
+    }
+
+    /// Replaces the node with `nodes`.
+    pub fn replace_with<I>(&mut self, nodes: I)
     where
-        Self: Sized,
+        I: IntoIterator,
+        I::Item: Into<C>,
     {
-        walk_at_rule_mut(self, at_rule);
+        self.edit.before.extend(nodes.into_iter().map(Into::into));
+        self.edit.remove = true;
+    }
+
+    /// Inserts `node` right before the node.
+    pub fn insert_before(&mut self, node: impl Into<C>) {
+        self.edit.before.push(node.into());
+    }
+
+    /// Inserts `node` right after the node, so before the nodes inserted
+    /// after it earlier.
+    pub fn insert_after(&mut self, node: impl Into<C>) {
+        self.edit.after.insert(0, node.into());
     }
 
-    fn visit_declaration(&mut self, _: &mut Declaration) {}
+    /// Inserts a copy of the node right before it and returns the copy.
+    pub fn clone_before(&mut self) -> &mut T {
+        self.edit.before.push(self.node.clone().into());
+        T::from_sibling_mut(self.edit.before.last_mut().unwrap()).unwrap()
+    }
+}
+
+impl<T, C> Deref for NodeMut<'_, T, C> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        self.node
+    }
+}
 
-    fn visit_comment(&mut self, _: &mut Comment) {}
+impl<T, C> DerefMut for NodeMut<'_, T, C> {
+    fn deref_mut(&mut self) -> &mut T {
+        self.node
+    }
 }
 
-pub fn walk_root_mut<V: VisitorMut>(visitor: &mut V, root: &mut Root) {
-    for child in root.nodes.iter_mut() {
-        match child {
-            RootChild::Rule(rule) => visitor.visit_rule(rule),
-            RootChild::AtRule(at_rule) => visitor.visit_at_rule(at_rule),
-            RootChild::Comment(comment) => visitor.visit_comment(comment),
+impl<C> Edit<C> {
+    /// Applies the edit to the node at `index` of `nodes`, returning the
+    /// index of the next node to visit.
+    fn apply(self, nodes: &mut Vec<C>, index: usize) -> usize {
+        let mut index = index;
+        let before = self.before.len();
+        nodes.splice(index..index, self.before);
+        index += before;
+        if self.remove {
+            nodes.remove(index);
+        } else {
+            index += 1;
         }
+        let after = self.after.len();
+        nodes.splice(index..index, self.after);
+
+        index + after
     }
 }
 
-pub fn walk_rule_mut<V: VisitorMut>(visitor: &mut V, rule: &mut Rule) {
-    for child in rule.nodes.iter_mut() {
-        match child {
-            BlockChild::Declaration(decl) => visitor.visit_declaration(decl),
-            BlockChild::AtRule(at_rule) => visitor.visit_at_rule(at_rule),
-            BlockChild::Comment(comment) => visitor.visit_comment(comment),
+/// Walks the tree depth first, applying the changes made through
+/// [`NodeMut`]. Returns `Stop` if a hook stopped the walk, after applying the
+/// changes made so far.
+pub fn walk_root_mut<V: VisitorMut>(visitor: &mut V, root: &mut Root) -> VisitAction {
+    match visitor.visit_root(root) {
+        VisitAction::Continue => {
+            let mut index = 0;
+            while index < root.nodes.len() {
+                let mut edit = Edit::default();
+                let action = match &mut root.nodes[index] {
+                    RootChild::Rule(node) => walk_rule_node_mut(
+                        visitor,
+                        &mut NodeMut {
+                            node,
+                            edit: &mut edit,
+                        },
+                    ),
+                    RootChild::AtRule(node) => walk_at_rule_node_mut(
+                        visitor,
+                        &mut NodeMut {
+                            node,
+                            edit: &mut edit,
+                        },
+                    ),
+                    RootChild::Comment(node) => visitor.visit_comment(&mut NodeMut {
+                        node,
+                        edit: &mut edit,
+                    }),
+                };
+                index = edit.apply(&mut root.nodes, index);
+                if action == VisitAction::Stop {
+                    return VisitAction::Stop;
+                }
+            }
         }
+        VisitAction::SkipChildren => {}
+        VisitAction::Stop => return VisitAction::Stop,
     }
+    visitor.leave_root(root)
 }
 
-pub fn walk_at_rule_mut<V: VisitorMut>(visitor: &mut V, at_rule: &mut AtRule) {
-    for child in at_rule.nodes.iter_mut() {
-        match child {
-            BlockChild::Declaration(decl) => visitor.visit_declaration(decl),
-            BlockChild::AtRule(at_rule) => visitor.visit_at_rule(at_rule),
-            BlockChild::Comment(comment) => visitor.visit_comment(comment),
+/// Walks a rule that is not part of a root, such as one returned by
+/// `Parser::parse_rule`. Changes around the rule itself, like removing it,
+/// are dropped as it has no parent to apply them to.
+pub fn walk_rule_mut<V: VisitorMut>(visitor: &mut V, rule: &mut Rule) -> VisitAction {
+    let mut edit = Edit::<BlockChild>::default();
+    walk_rule_node_mut(
+        visitor,
+        &mut NodeMut {
+            node: rule,
+            edit: &mut edit,
+        },
+    )
+}
+
+/// Walks an at-rule that is not part of a root, like [`walk_rule_mut`].
+pub fn walk_at_rule_mut<V: VisitorMut>(visitor: &mut V, at_rule: &mut AtRule) -> VisitAction {
+    let mut edit = Edit::<BlockChild>::default();
+    walk_at_rule_node_mut(
+        visitor,
+        &mut NodeMut {
+            node: at_rule,
+            edit: &mut edit,
+        },
+    )
+}
+
+fn walk_rule_node_mut<'a, V: VisitorMut, C: Sibling<'a>>(
+    visitor: &mut V,
+    rule: &mut NodeMut<Rule<'a>, C>,
+) -> VisitAction {
+    match visitor.visit_rule(rule) {
+        VisitAction::Continue => {
+            if walk_block_mut(visitor, &mut rule.nodes) == VisitAction::Stop {
+                return VisitAction::Stop;
+            }
         }
+        VisitAction::SkipChildren => {}
+        VisitAction::Stop => return VisitAction::Stop,
     }
+    visitor.leave_rule(rule)
+}
+
+fn walk_at_rule_node_mut<'a, V: VisitorMut, C: Sibling<'a>>(
+    visitor: &mut V,
+    at_rule: &mut NodeMut<AtRule<'a>, C>,
+) -> VisitAction {
+    match visitor.visit_at_rule(at_rule) {
+        VisitAction::Continue => {
+            if let Some(nodes) = &mut at_rule.nodes {
+                if walk_block_mut(visitor, nodes) == VisitAction::Stop {
+                    return VisitAction::Stop;
+                }
+            }
+        }
+        VisitAction::SkipChildren => {}
+        VisitAction::Stop => return VisitAction::Stop,
+    }
+    visitor.leave_at_rule(at_rule)
+}
+
+fn walk_block_mut<V: VisitorMut>(visitor: &mut V, nodes: &mut Vec<BlockChild>) -> VisitAction {
+    let mut index = 0;
+    while index < nodes.len() {
+        let mut edit = Edit::default();
+        let action = match &mut nodes[index] {
+            BlockChild::Rule(node) => walk_rule_node_mut(
+                visitor,
+                &mut NodeMut {
+                    node,
+                    edit: &mut edit,
+                },
+            ),
+            BlockChild::Declaration(node) => visitor.visit_declaration(&mut NodeMut {
+                node,
+                edit: &mut edit,
+            }),
+            BlockChild::AtRule(node) => walk_at_rule_node_mut(
+                visitor,
+                &mut NodeMut {
+                    node,
+                    edit: &mut edit,
+                },
+            ),
+            BlockChild::Comment(node) => visitor.visit_comment(&mut NodeMut {
+                node,
+                edit: &mut edit,
+            }),
+        };
+        index = edit.apply(nodes, index);
+        if action == VisitAction::Stop {
+            return VisitAction::Stop;
+        }
+    }
+    VisitAction::Continue
 }
 
 #[cfg(test)]
@@ -133,29 +503,29 @@ mod tests {
         }
 
         impl Visitor for Counter {
-            fn visit_root(&mut self, root: &Root) {
-                walk_root(self, root);
-            }
-
-            fn visit_rule(&mut self, rule: &Rule) {
+            fn visit_rule(&mut self, _: &Rule) -> VisitAction {
                 self.count += 1;
-                walk_rule(self, rule);
+                VisitAction::Continue
             }
 
-            fn visit_at_rule(&mut self, at_rule: &AtRule) {
+            fn visit_at_rule(&mut self, _: &AtRule) -> VisitAction {
                 self.count += 1;
-                walk_at_rule(self, at_rule);
+                VisitAction::Continue
             }
 
-            fn visit_declaration(&mut self, _: &Declaration) {
+            fn visit_declaration(&mut self, _: &Declaration) -> VisitAction {
                 self.count += 1;
+                VisitAction::Continue
             }
         }
         let mut p = Parser::new("foo { hello: world ; foo : bar; @foo { a:b } }");
 
         let mut c = Counter::new();
 
-        c.visit_root(&p.parse().unwrap());
+        assert_eq!(
+            walk_root(&mut c, &p.parse().unwrap()),
+            VisitAction::Continue
+        );
 
         assert_eq!(c.count, 5);
     }
@@ -175,32 +545,40 @@ mod tests {
         }
 
         impl Visitor for Minifier {
-            fn visit_root(&mut self, root: &Root) {
-                walk_root(self, root);
+            fn visit_rule(&mut self, rule: &Rule) -> VisitAction {
+                self.css.push_str(&format!("{}{{", rule.selector));
+                VisitAction::Continue
             }
 
-            fn visit_rule(&mut self, rule: &Rule) {
-                self.css.push_str(&format!("{}{{", rule.selector));
-                walk_rule(self, rule);
-                self.css.push_str("}");
+            fn leave_rule(&mut self, _: &Rule) -> VisitAction {
+                self.css.push('}');
+                VisitAction::Continue
             }
 
-            fn visit_at_rule(&mut self, at_rule: &AtRule) {
+            fn visit_at_rule(&mut self, at_rule: &AtRule) -> VisitAction {
                 self.css
                     .push_str(&format!("@{} {}{{", at_rule.name, at_rule.params));
-                walk_at_rule(self, at_rule);
-                self.css.push_str("}");
+                VisitAction::Continue
+            }
+
+            fn leave_at_rule(&mut self, _: &AtRule) -> VisitAction {
+                self.css.push('}');
+                VisitAction::Continue
             }
 
-            fn visit_declaration(&mut self, decl: &Declaration) {
+            fn visit_declaration(&mut self, decl: &Declaration) -> VisitAction {
                 self.css.push_str(&format!("{}:{};", decl.prop, decl.value));
+                VisitAction::Continue
             }
         }
         let mut p = Parser::new("/* comment */foo { hello: world ; foo : bar; @foo { a:b } }");
 
         let mut m = Minifier::new();
 
-        m.visit_root(&p.parse().unwrap());
+        assert_eq!(
+            walk_root(&mut m, &p.parse().unwrap()),
+            VisitAction::Continue
+        );
 
         assert_eq!(m.css, "foo{hello:world;foo:bar;@foo {a:b;}}");
     }
@@ -216,21 +594,20 @@ mod tests {
         }
 
         impl VisitorMut for Prefixer {
-            fn visit_root(&mut self, root: &mut Root) {
-                walk_root_mut(self, root);
+            fn visit_rule<'a, C: Sibling<'a>>(
+                &mut self,
+                rule: &mut NodeMut<Rule<'a>, C>,
+            ) -> VisitAction {
+                rule.selector = format!("-foo-{}", rule.selector).into();
+                VisitAction::Continue
             }
 
-            fn visit_rule(&mut self, rule: &mut Rule) {
-                rule.selector = format!("-foo-{}", rule.selector);
-                walk_rule_mut(self, rule);
-            }
-
-            fn visit_at_rule(&mut self, at_rule: &mut AtRule) {
-                walk_at_rule_mut(self, at_rule);
-            }
-
-            fn visit_declaration(&mut self, decl: &mut Declaration) {
-                decl.prop = format!("-foo-{}", decl.prop);
+            fn visit_declaration<'a>(
+                &mut self,
+                decl: &mut NodeMut<Declaration<'a>, BlockChild<'a>>,
+            ) -> VisitAction {
+                decl.prop = format!("-foo-{}", decl.prop).into();
+                VisitAction::Continue
             }
         }
 
@@ -240,20 +617,262 @@ mod tests {
 
         let mut root = p.parse().unwrap();
 
-        m.visit_root(&mut root);
+        assert_eq!(walk_root_mut(&mut m, &mut root), VisitAction::Continue);
+        root.clear_raws();
 
         assert_eq!(
             root,
             root!(
                 0,
-                19,
+                20,
                 vec![root_rule!(
                     0,
-                    19,
+                    20,
                     "-foo-foo",
-                    vec![decl!(6, 17, "-foo-hello", "world")]
+                    vec![decl!(6, 18, "-foo-hello", "world")]
                 )]
             )
         );
     }
+
+    #[test]
+    fn visit_edits() {
+        pub struct Editor {}
+
+        impl VisitorMut for Editor {
+            fn visit_rule<'a, C: Sibling<'a>>(
+                &mut self,
+                rule: &mut NodeMut<Rule<'a>, C>,
+            ) -> VisitAction {
+                if rule.selector == "a" {
+                    rule.prepend(decl!(0, 0, "first", "1"));
+                    rule.insert_after(Parser::new("after{}").parse_rule().unwrap());
+                }
+                VisitAction::Continue
+            }
+
+            fn visit_declaration<'a>(
+                &mut self,
+                decl: &mut NodeMut<Declaration<'a>, BlockChild<'a>>,
+            ) -> VisitAction {
+                match &*decl.prop {
+                    "remove" => decl.remove(),
+                    "margin" => decl.replace_with([
+                        decl!(0, 0, "margin-top", "0"),
+                        decl!(0, 0, "margin-bottom", "0"),
+                    ]),
+                    "display" => {
+                        decl.clone_before().value = "-webkit-box".into();
+                        decl.insert_after(comment!(0, 0, "flex"));
+                    }
+                    _ => {}
+                }
+                VisitAction::Continue
+            }
+
+            fn visit_comment<'a, C: Sibling<'a>>(
+                &mut self,
+                comment: &mut NodeMut<Comment<'a>, C>,
+            ) -> VisitAction {
+                comment.remove();
+                VisitAction::Continue
+            }
+        }
+
+        let mut root = Parser::new("/* x */a{remove:1;margin:0;display:flex}b{remove:2}")
+            .parse()
+            .unwrap();
+        assert_eq!(
+            walk_root_mut(&mut Editor {}, &mut root),
+            VisitAction::Continue
+        );
+        root.clear_raws();
+
+        assert_eq!(
+            root,
+            root!(
+                0,
+                51,
+                vec![
+                    root_rule!(
+                        7,
+                        40,
+                        "a",
+                        vec![
+                            decl!(0, 0, "first", "1"),
+                            decl!(0, 0, "margin-top", "0"),
+                            decl!(0, 0, "margin-bottom", "0"),
+                            decl!(27, 39, "display", "-webkit-box"),
+                            decl!(27, 39, "display", "flex"),
+                            comment!(0, 0, "flex"),
+                        ]
+                    ),
+                    root_rule!(0, 7, "after", vec![]),
+                    root_rule!(40, 51, "b", vec![]),
+                ]
+            )
+        );
+    }
+
+    #[test]
+    fn visit_actions() {
+        pub struct Tracer {
+            pub events: Vec<String>,
+        }
+
+        impl Visitor for Tracer {
+            fn visit_root(&mut self, _: &Root) -> VisitAction {
+                self.events.push("root".to_string());
+                VisitAction::Continue
+            }
+
+            fn leave_root(&mut self, _: &Root) -> VisitAction {
+                self.events.push("/root".to_string());
+                VisitAction::Continue
+            }
+
+            fn visit_rule(&mut self, rule: &Rule) -> VisitAction {
+                self.events.push(rule.selector.to_string());
+                match &*rule.selector {
+                    "skip" => VisitAction::SkipChildren,
+                    _ => VisitAction::Continue,
+                }
+            }
+
+            fn leave_rule(&mut self, rule: &Rule) -> VisitAction {
+                self.events.push(format!("/{}", rule.selector));
+                VisitAction::Continue
+            }
+
+            fn visit_at_rule(&mut self, at_rule: &AtRule) -> VisitAction {
+                self.events.push(format!("@{}", at_rule.name));
+                VisitAction::Continue
+            }
+
+            fn leave_at_rule(&mut self, at_rule: &AtRule) -> VisitAction {
+                self.events.push(format!("/@{}", at_rule.name));
+                VisitAction::Continue
+            }
+
+            fn visit_declaration(&mut self, decl: &Declaration) -> VisitAction {
+                self.events.push(decl.prop.to_string());
+                match &*decl.prop {
+                    "stop" => VisitAction::Stop,
+                    _ => VisitAction::Continue,
+                }
+            }
+        }
+
+        let root = Parser::new("a{b:c}skip{d:e}@m{f{g:h}}").parse().unwrap();
+        let mut tracer = Tracer { events: vec![] };
+        assert_eq!(walk_root(&mut tracer, &root), VisitAction::Continue);
+        assert_eq!(
+            tracer.events,
+            ["root", "a", "b", "/a", "skip", "/skip", "@m", "f", "g", "/f", "/@m", "/root"]
+        );
+
+        let root = Parser::new("a{b{stop:c;d:e}}f{}").parse().unwrap();
+        let mut tracer = Tracer { events: vec![] };
+        assert_eq!(walk_root(&mut tracer, &root), VisitAction::Stop);
+        assert_eq!(tracer.events, ["root", "a", "b", "stop"]);
+    }
+
+    #[test]
+    fn visit_mut_stop() {
+        pub struct Remover {}
+
+        impl VisitorMut for Remover {
+            fn visit_rule<'a, C: Sibling<'a>>(
+                &mut self,
+                rule: &mut NodeMut<Rule<'a>, C>,
+            ) -> VisitAction {
+                match &*rule.selector {
+                    "skip" => VisitAction::SkipChildren,
+                    _ => VisitAction::Continue,
+                }
+            }
+
+            fn leave_rule<'a, C: Sibling<'a>>(
+                &mut self,
+                rule: &mut NodeMut<Rule<'a>, C>,
+            ) -> VisitAction {
+                if rule.nodes.is_empty() {
+                    rule.remove();
+                }
+                VisitAction::Continue
+            }
+
+            fn visit_declaration<'a>(
+                &mut self,
+                decl: &mut NodeMut<Declaration<'a>, BlockChild<'a>>,
+            ) -> VisitAction {
+                decl.remove();
+                match &*decl.prop {
+                    "stop" => VisitAction::Stop,
+                    _ => VisitAction::Continue,
+                }
+            }
+        }
+
+        let mut root = Parser::new("a{b:c}skip{d:e}f{stop:g;h:i}j{k:l}")
+            .parse()
+            .unwrap();
+        assert_eq!(walk_root_mut(&mut Remover {}, &mut root), VisitAction::Stop);
+        root.clear_raws();
+
+        assert_eq!(
+            root,
+            root!(
+                0,
+                34,
+                vec![
+                    root_rule!(6, 15, "skip", vec![decl!(11, 14, "d", "e")]),
+                    root_rule!(15, 28, "f", vec![decl!(24, 27, "h", "i")]),
+                    root_rule!(28, 34, "j", vec![decl!(30, 33, "k", "l")]),
+                ]
+            )
+        );
+    }
+
+    #[test]
+    fn visit_fragment_mut() {
+        pub struct Remover {}
+
+        impl VisitorMut for Remover {
+            fn visit_rule<'a, C: Sibling<'a>>(
+                &mut self,
+                rule: &mut NodeMut<Rule<'a>, C>,
+            ) -> VisitAction {
+                rule.remove();
+                VisitAction::Continue
+            }
+
+            fn visit_declaration<'a>(
+                &mut self,
+                decl: &mut NodeMut<Declaration<'a>, BlockChild<'a>>,
+            ) -> VisitAction {
+                decl.clone_before().prop = "x".into();
+                VisitAction::Continue
+            }
+        }
+
+        let mut rule = Parser::new("a{b:c;d{}}").parse_rule().unwrap();
+        assert_eq!(
+            walk_rule_mut(&mut Remover {}, &mut rule),
+            VisitAction::Continue
+        );
+        rule.clear_raws();
+        assert_eq!(rule.selector, "a");
+        assert_eq!(
+            rule.nodes,
+            vec![decl!(2, 5, "x", "c"), decl!(2, 5, "b", "c")]
+        );
+
+        let mut at_rule = Parser::new("@media print{a{}}").parse_at_rule().unwrap();
+        assert_eq!(
+            walk_at_rule_mut(&mut Remover {}, &mut at_rule),
+            VisitAction::Continue
+        );
+        assert_eq!(at_rule.nodes, Some(vec![]));
+    }
 }